@@ -223,9 +223,13 @@ impl ASCIIGenerator {
         if resp.is_empty() {
             return;
         }
-        let msg = crate::msg::get_root_as_message(&resp);
-        if let Some(data) = msg.msg() {
-            eprint!("{}", data);
+        match crate::msg::root_as_message(&resp) {
+            Ok(msg) => {
+                if let Some(data) = msg.msg() {
+                    eprint!("{}", data);
+                }
+            }
+            Err(err) => eprintln!("invalid message: {}", err),
         }
     }
 }
@@ -422,7 +426,7 @@ mod msg {
     )]
     include!("./schema/model_generated.rs");
 
-    pub use model::get_root_as_message;
+    pub use model::root_as_message;
     pub use model::{Message, MessageArgs, MessageBuilder, MessageType};
 
     #[cfg(test)]
@@ -430,7 +434,7 @@ mod msg {
         use flatbuffers::FlatBufferBuilder;
         #[test]
         fn message_create() {
-            use super::get_root_as_message;
+            use super::root_as_message;
             use super::{Message, MessageArgs, MessageType};
             let msgs = ["a", "b", "c", "d"];
             for msg in &msgs {
@@ -445,7 +449,7 @@ mod msg {
                 );
                 b.finish(data, None);
                 let buf = b.finished_data();
-                let got = get_root_as_message(buf);
+                let got = root_as_message(buf).unwrap();
                 assert_eq!(msg, &got.msg().unwrap());
                 assert_eq!(0, got.id());
                 assert_eq!(MessageType::Hello, got.msg_type());
@@ -454,7 +458,7 @@ mod msg {
         }
         #[test]
         fn message_builder() {
-            use super::get_root_as_message;
+            use super::root_as_message;
             use super::MessageType;
             let mut b = FlatBufferBuilder::new();
             let bmsg = b.create_string("a");
@@ -465,7 +469,7 @@ mod msg {
             let data = mb.finish();
             b.finish(data, None);
             let buf = b.finished_data();
-            let got = get_root_as_message(buf);
+            let got = root_as_message(buf).unwrap();
             assert_eq!("a", got.msg().unwrap());
             assert_eq!(1000, got.id());
             assert_eq!(MessageType::Goodbye, got.msg_type());
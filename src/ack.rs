@@ -0,0 +1,101 @@
+// SPDX-License-Identifier: Apache-2.0 AND MIT
+//! `Acker` struct
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// A detached acknowledgement handle of the [Message].
+///
+/// It's taken out of the [Message], once, through the [Message::acker]
+/// method and sent to other tasks to acknowledge the message from there.
+/// The message is nacked, and requeued, when the handle is dropped without
+/// acknowledging, nacking or rejecting it.  The other chunks of the
+/// reassembled message are settled along with it.
+///
+/// [Message]: ../message/struct.Message.html
+/// [Message::acker]: ../message/struct.Message.html#method.acker
+pub struct Acker {
+    ch: lapin::Channel,
+    tag: u64,
+    chunks: Vec<u64>,
+    settled: AtomicBool,
+}
+
+impl Acker {
+    pub(crate) fn new(ch: lapin::Channel, tag: u64, chunks: Vec<u64>) -> Self {
+        Self {
+            ch,
+            tag,
+            chunks,
+            settled: AtomicBool::new(false),
+        }
+    }
+    #[inline]
+    pub fn delivery_tag(&self) -> u64 {
+        self.tag
+    }
+    /// Acknowledge the message.
+    pub async fn ack(self) -> crate::Result<()> {
+        self.settle();
+        for tag in self.tags() {
+            self.ch
+                .basic_ack(tag, lapin::options::BasicAckOptions::default())
+                .await
+                .map_err(crate::Error::from)?;
+        }
+        Ok(())
+    }
+    /// Nack the message, and requeue it in case of `requeue` is true.
+    pub async fn nack(self, requeue: bool) -> crate::Result<()> {
+        self.settle();
+        let opts = lapin::options::BasicNackOptions {
+            multiple: false,
+            requeue,
+        };
+        for tag in self.tags() {
+            self.ch
+                .basic_nack(tag, opts.clone())
+                .await
+                .map_err(crate::Error::from)?;
+        }
+        Ok(())
+    }
+    /// Reject the message, and requeue it in case of `requeue` is true.
+    pub async fn reject(self, requeue: bool) -> crate::Result<()> {
+        self.settle();
+        let opts = lapin::options::BasicRejectOptions { requeue };
+        for tag in self.tags() {
+            self.ch
+                .basic_reject(tag, opts.clone())
+                .await
+                .map_err(crate::Error::from)?;
+        }
+        Ok(())
+    }
+    /// Marks the message settled and returns true in case it's not yet.
+    pub(crate) fn settle(&self) -> bool {
+        !self.settled.swap(true, Ordering::SeqCst)
+    }
+    /// Returns the delivery tags of the message and the other chunks.
+    fn tags(&self) -> Vec<u64> {
+        std::iter::once(self.tag)
+            .chain(self.chunks.iter().copied())
+            .collect()
+    }
+}
+
+impl Drop for Acker {
+    /// Nacks and requeues the message which is not settled yet.
+    ///
+    /// The nack frame is sent on the method call, so that the returned
+    /// confirmation is just dropped here.
+    fn drop(&mut self) {
+        if self.settle() {
+            let opts = lapin::options::BasicNackOptions {
+                multiple: false,
+                requeue: true,
+            };
+            for tag in self.tags() {
+                let _confirmation = self.ch.basic_nack(tag, opts.clone());
+            }
+        }
+    }
+}
@@ -0,0 +1,384 @@
+// SPDX-License-Identifier: Apache-2.0 AND MIT
+//! `Batches` struct and `BatchProcess` trait for the batch consumption
+use async_trait::async_trait;
+use futures::stream::{self, BoxStream, Stream, StreamExt};
+use futures_timer::Delay;
+use std::collections::BTreeSet;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+/// A trait to process the batch of the [Message]s, e.g. for the database
+/// bulk inserts.
+///
+/// [Message]: ../message/struct.Message.html
+#[async_trait]
+pub trait BatchProcess {
+    /// Async method to process a batch.
+    async fn process(&self, batch: &[crate::Message]) -> Result<(), crate::MessageError>;
+}
+
+/// A batching adapter over the [Consumer], which is a [Stream] of the
+/// messages batched up to the `size` messages, or those received in the
+/// `window` since the first message of the batch.
+///
+/// The messages are prepared the same way as the [Consumer::run] method
+/// does, i.e. peeked, reassembled, decrypted, upcasted and validated,
+/// before those are batched.  The batch is settled with the single
+/// `multiple` frame up to its last message, in case no other delivery is
+/// left unsettled up to it, e.g. the messages of the previous batches or
+/// the ones taken the [Acker] handle.  Otherwise, as well as for the
+/// reassembled messages, those are settled one by one not to settle the
+/// other messages of the channel.
+///
+/// [Consumer]: ../consume/struct.Consumer.html
+/// [Consumer::run]: ../consume/struct.Consumer.html#method.run
+/// [Stream]: https://docs.rs/futures/latest/futures/stream/trait.Stream.html
+/// [Acker]: ../ack/struct.Acker.html
+pub struct Batches {
+    ch: lapin::Channel,
+    no_ack: bool,
+    multiple: bool,
+    unsettled: Mutex<BTreeSet<u64>>,
+    batched: Batched<BoxStream<'static, crate::Result<crate::Message>>>,
+}
+
+impl Batches {
+    pub fn new(consumer: crate::Consumer, size: usize, window: Duration) -> Self {
+        let ch = consumer.channel().clone();
+        let no_ack = consumer.no_ack();
+        let multiple = !consumer.reassembles();
+        let prepared = stream::unfold(consumer, |mut consumer| async move {
+            let msg = consumer.prepared().await?;
+            Some((msg, consumer))
+        });
+        Self {
+            ch,
+            no_ack,
+            multiple,
+            unsettled: Mutex::new(BTreeSet::new()),
+            batched: Batched::new(prepared.boxed(), size, window),
+        }
+    }
+    /// Process the batches with the [BatchProcess] trait object, and
+    /// settle each batch with the result.
+    ///
+    /// The batch is acknowledged on success, as well as on the
+    /// [MessageError::Drop], or nacked with the requeue flag of the error.
+    ///
+    /// [BatchProcess]: trait.BatchProcess.html
+    /// [MessageError::Drop]: ../message/enum.MessageError.html#variant.Drop
+    pub async fn run(
+        &mut self,
+        processor: Arc<dyn BatchProcess + Send + Sync>,
+    ) -> crate::Result<()> {
+        while let Some(batch) = self.next().await {
+            let batch = batch?;
+            match processor.process(&batch).await {
+                Ok(()) => self.ack(&batch).await?,
+                Err(err) => match requeue(&err) {
+                    Some(requeue) => self.nack(&batch, requeue).await?,
+                    None => self.ack(&batch).await?,
+                },
+            }
+        }
+        Ok(())
+    }
+    /// Acknowledges the batch, as well as the other chunks of each
+    /// message.
+    pub async fn ack(&self, batch: &[crate::Message]) -> crate::Result<()> {
+        let (tags, multiple) = self.settle(batch);
+        let opts = lapin::options::BasicAckOptions { multiple };
+        for tag in tags {
+            self.ch
+                .basic_ack(tag, opts.clone())
+                .await
+                .map_err(crate::Error::from)?;
+        }
+        Ok(())
+    }
+    /// Nacks the batch, as well as the other chunks of each message.
+    pub async fn nack(&self, batch: &[crate::Message], requeue: bool) -> crate::Result<()> {
+        let (tags, multiple) = self.settle(batch);
+        let opts = lapin::options::BasicNackOptions { multiple, requeue };
+        for tag in tags {
+            self.ch
+                .basic_nack(tag, opts.clone())
+                .await
+                .map_err(crate::Error::from)?;
+        }
+        Ok(())
+    }
+    /// Marks the batch settled and returns the delivery tags to settle,
+    /// or the last one alone with true to settle those with the
+    /// `multiple` flag.
+    fn settle(&self, batch: &[crate::Message]) -> (Vec<u64>, bool) {
+        let tags: Vec<_> = batch
+            .iter()
+            .filter(|msg| msg.settle())
+            .flat_map(tags)
+            .collect();
+        if self.no_ack {
+            return (Vec::new(), false);
+        }
+        let mut unsettled = self.lock();
+        for tag in &tags {
+            unsettled.remove(tag);
+        }
+        match multiple(&unsettled, &tags) {
+            Some(last) if self.multiple => (vec![last], true),
+            _ => (tags, false),
+        }
+    }
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeSet<u64>> {
+        self.unsettled
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Returns the delivery tags of the message, including its other chunks.
+fn tags(msg: &crate::Message) -> impl Iterator<Item = u64> + '_ {
+    std::iter::once(msg.delivery_tag()).chain(msg.chunks().iter().copied())
+}
+
+/// Returns the last delivery tag of the batch `tags`, in case none of the
+/// other `unsettled` deliveries precedes it.  The deliveries missing from
+/// those are the ones the [Consumer] has already settled, e.g. on the
+/// peek failure.
+///
+/// [Consumer]: ../consume/struct.Consumer.html
+fn multiple(unsettled: &BTreeSet<u64>, tags: &[u64]) -> Option<u64> {
+    let last = *tags.iter().max()?;
+    match unsettled.range(..last).next() {
+        Some(_) => None,
+        None => Some(last),
+    }
+}
+
+impl Stream for Batches {
+    type Item = crate::Result<Vec<crate::Message>>;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = Pin::new(&mut self.batched).poll_next(cx);
+        if let Poll::Ready(Some(Ok(batch))) = &poll {
+            if !self.no_ack {
+                self.lock().extend(batch.iter().flat_map(tags));
+            }
+        }
+        poll
+    }
+}
+
+/// A [Stream] of the batches of the `stream` items.
+///
+/// [Stream]: https://docs.rs/futures/latest/futures/stream/trait.Stream.html
+struct Batched<S> {
+    stream: S,
+    size: usize,
+    window: Duration,
+    buf: Vec<crate::Message>,
+    delay: Option<Delay>,
+    done: bool,
+}
+
+impl<S> Batched<S> {
+    fn new(stream: S, size: usize, window: Duration) -> Self {
+        Self {
+            stream,
+            size: size.max(1),
+            window,
+            buf: Vec::new(),
+            delay: None,
+            done: false,
+        }
+    }
+    fn flush(&mut self) -> Vec<crate::Message> {
+        self.delay = None;
+        std::mem::take(&mut self.buf)
+    }
+}
+
+impl<S> Stream for Batched<S>
+where
+    S: Stream<Item = crate::Result<crate::Message>> + Unpin,
+{
+    type Item = crate::Result<Vec<crate::Message>>;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        while !self.done {
+            match Pin::new(&mut self.stream).poll_next(cx) {
+                Poll::Ready(Some(Ok(msg))) => {
+                    if self.buf.is_empty() {
+                        self.delay = Some(Delay::new(self.window));
+                    }
+                    self.buf.push(msg);
+                    if self.buf.len() >= self.size {
+                        return Poll::Ready(Some(Ok(self.flush())));
+                    }
+                }
+                Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(err))),
+                Poll::Ready(None) => self.done = true,
+                Poll::Pending => {
+                    let elapsed = match &mut self.delay {
+                        Some(delay) => Pin::new(delay).poll(cx).is_ready(),
+                        None => false,
+                    };
+                    if elapsed {
+                        return Poll::Ready(Some(Ok(self.flush())));
+                    }
+                    return Poll::Pending;
+                }
+            }
+        }
+        if self.buf.is_empty() {
+            Poll::Ready(None)
+        } else {
+            Poll::Ready(Some(Ok(self.flush())))
+        }
+    }
+}
+
+/// Returns the requeue flag to nack the batch with, or `None` to
+/// acknowledge it.
+fn requeue(err: &crate::MessageError) -> Option<bool> {
+    match err {
+        crate::MessageError::Drop => None,
+        crate::MessageError::Reject { requeue, .. } | crate::MessageError::Nack { requeue, .. } => {
+            Some(*requeue)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::MessageError;
+    use futures::channel::mpsc;
+    use futures::stream::{self, StreamExt};
+    use std::time::{Duration, Instant};
+    fn message(tag: u64) -> crate::Result<crate::Message> {
+        let props = lapin::BasicProperties::default();
+        Ok(crate::message::test_message(tag, "", "", props, b""))
+    }
+    fn tags(batch: Option<crate::Result<Vec<crate::Message>>>) -> Vec<u64> {
+        batch
+            .unwrap()
+            .unwrap()
+            .iter()
+            .map(crate::Message::delivery_tag)
+            .collect()
+    }
+    #[test]
+    fn flush_at_size() {
+        let messages = stream::iter((1..=5).map(message));
+        let mut batched = super::Batched::new(messages, 2, Duration::from_secs(60));
+        futures::executor::block_on(async {
+            assert_eq!(vec![1, 2], tags(batched.next().await));
+            assert_eq!(vec![3, 4], tags(batched.next().await));
+            assert_eq!(vec![5], tags(batched.next().await));
+            assert!(batched.next().await.is_none());
+        });
+    }
+    #[test]
+    fn flush_on_window() {
+        let (tx, rx) = mpsc::unbounded();
+        let window = Duration::from_millis(50);
+        let mut batched = super::Batched::new(rx, 3, window);
+        tx.unbounded_send(message(1)).unwrap();
+        tx.unbounded_send(message(2)).unwrap();
+        let start = Instant::now();
+        futures::executor::block_on(async {
+            assert_eq!(vec![1, 2], tags(batched.next().await));
+            assert!(start.elapsed() >= window);
+            tx.unbounded_send(message(3)).unwrap();
+            drop(tx);
+            assert_eq!(vec![3], tags(batched.next().await));
+            assert!(batched.next().await.is_none());
+        });
+    }
+    #[test]
+    fn requeue() {
+        struct Test {
+            name: &'static str,
+            err: MessageError,
+            want: Option<bool>,
+        }
+        let tests = [
+            Test {
+                name: "drop",
+                err: MessageError::Drop,
+                want: None,
+            },
+            Test {
+                name: "reject",
+                err: MessageError::reject(),
+                want: Some(false),
+            },
+            Test {
+                name: "reject with requeue",
+                err: MessageError::reject().with_requeue(true),
+                want: Some(true),
+            },
+            Test {
+                name: "nack with requeue",
+                err: MessageError::nack().with_requeue(true),
+                want: Some(true),
+            },
+        ];
+        for t in &tests {
+            assert_eq!(t.want, super::requeue(&t.err), "{}", t.name);
+        }
+    }
+    #[test]
+    fn multiple() {
+        struct Test {
+            name: &'static str,
+            unsettled: &'static [u64],
+            tags: &'static [u64],
+            want: Option<u64>,
+        }
+        let tests = [
+            Test {
+                name: "only unsettled batch",
+                unsettled: &[],
+                tags: &[1, 2, 3],
+                want: Some(3),
+            },
+            Test {
+                name: "later batch unsettled",
+                unsettled: &[4, 5],
+                tags: &[1, 2, 3],
+                want: Some(3),
+            },
+            Test {
+                name: "previous batch unsettled",
+                unsettled: &[1, 2],
+                tags: &[3, 4],
+                want: None,
+            },
+            Test {
+                name: "taken acker in the batch",
+                unsettled: &[2],
+                tags: &[1, 3],
+                want: None,
+            },
+            Test {
+                name: "settled by the consumer in between",
+                unsettled: &[],
+                tags: &[1, 3],
+                want: Some(3),
+            },
+            Test {
+                name: "empty batch",
+                unsettled: &[1],
+                tags: &[],
+                want: None,
+            },
+        ];
+        for t in &tests {
+            let unsettled = t.unsettled.iter().copied().collect();
+            let got = super::multiple(&unsettled, t.tags);
+            assert_eq!(t.want, got, "{}", t.name);
+        }
+    }
+}
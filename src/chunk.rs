@@ -0,0 +1,365 @@
+// SPDX-License-Identifier: Apache-2.0 AND MIT
+//! `Reassembler` struct for the large message chunking
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// The header name carrying the transfer ID shared by all the chunks.
+pub const TRANSFER_ID_HEADER: &str = "x-transfer-id";
+/// The header name carrying the zero based chunk index.
+pub const CHUNK_INDEX_HEADER: &str = "x-chunk-index";
+/// The header name carrying the number of chunks of the transfer.
+pub const CHUNK_COUNT_HEADER: &str = "x-chunk-count";
+
+/// Default incomplete transfer timeout.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+/// Default memory bound of the incomplete transfers.
+pub const DEFAULT_MAX_BYTES: usize = 64 * 1024 * 1024;
+
+/// Returns the chunks of the `data`, each carrying the chunk headers
+/// on top of the provided properties.
+pub(crate) fn split(
+    props: lapin::BasicProperties,
+    data: Vec<u8>,
+    size: usize,
+) -> Vec<(lapin::BasicProperties, Vec<u8>)> {
+    if data.len() <= size {
+        return vec![(props, data)];
+    }
+    let id = transfer_id();
+    let chunks: Vec<_> = data.chunks(size).collect();
+    let count = chunks.len() as u32;
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(i, chunk)| {
+            let props = crate::message::with_header(
+                props.clone(),
+                TRANSFER_ID_HEADER,
+                lapin::types::AMQPValue::LongString(id.clone().into()),
+            );
+            let props = crate::message::with_header(
+                props,
+                CHUNK_INDEX_HEADER,
+                lapin::types::AMQPValue::LongUInt(i as u32),
+            );
+            let props = crate::message::with_header(
+                props,
+                CHUNK_COUNT_HEADER,
+                lapin::types::AMQPValue::LongUInt(count),
+            );
+            (props, chunk.to_vec())
+        })
+        .collect()
+}
+
+fn transfer_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let seq = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("{}-{:x}-{:x}", std::process::id(), now, seq)
+}
+
+/// A chunked message reassembler.
+///
+/// It keeps the chunks of the incomplete transfers in memory, up to the
+/// memory bound, and drops the transfers which don't complete within the
+/// timeout, checked on every push as well as by the [expire] method.
+/// The delivery tags of the dropped chunks are returned by the
+/// [take_dropped] method so that the caller can reject them.
+///
+/// [expire]: #method.expire
+/// [take_dropped]: #method.take_dropped
+pub struct Reassembler {
+    timeout: Duration,
+    max_bytes: usize,
+    bytes: usize,
+    transfers: HashMap<String, Transfer>,
+    dropped: Vec<u64>,
+}
+
+struct Transfer {
+    started: Instant,
+    count: u32,
+    bytes: usize,
+    chunks: BTreeMap<u32, Vec<u8>>,
+    tags: Vec<u64>,
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Self::new(DEFAULT_TIMEOUT, DEFAULT_MAX_BYTES)
+    }
+}
+
+impl Reassembler {
+    pub fn new(timeout: Duration, max_bytes: usize) -> Self {
+        Self {
+            timeout,
+            max_bytes,
+            bytes: 0,
+            transfers: HashMap::new(),
+            dropped: Vec::new(),
+        }
+    }
+    /// Push the message and returns the reassembled message, with the
+    /// delivery tags of the other chunks, once all the chunks arrived.
+    ///
+    /// The message without the chunk headers is returned as is.
+    pub fn push(&mut self, mut msg: crate::Message) -> Option<(crate::Message, Vec<u64>)> {
+        self.expire();
+        let (id, index, count) = match chunk(&msg) {
+            Some(chunk) => chunk,
+            None => return Some((msg, vec![])),
+        };
+        if index >= count {
+            self.dropped.push(msg.delivery_tag());
+            return None;
+        }
+        if matches!(self.transfers.get(&id), Some(t) if t.count != count) {
+            // The chunks of the transfer disagree on the chunk count.
+            self.drop_transfer(&id);
+            self.dropped.push(msg.delivery_tag());
+            return None;
+        }
+        let len = msg.data().len();
+        if len > self.max_bytes {
+            self.drop_transfer(&id);
+            self.dropped.push(msg.delivery_tag());
+            return None;
+        }
+        while self.bytes + len > self.max_bytes {
+            if !self.drop_oldest(&id) {
+                // The transfer itself doesn't fit in the memory bound.
+                self.drop_transfer(&id);
+                self.dropped.push(msg.delivery_tag());
+                return None;
+            }
+        }
+        let transfer = self
+            .transfers
+            .entry(id.clone())
+            .or_insert_with(|| Transfer {
+                started: Instant::now(),
+                count,
+                bytes: 0,
+                chunks: BTreeMap::new(),
+                tags: Vec::new(),
+            });
+        if let Some(old) = transfer.chunks.insert(index, msg.data().to_vec()) {
+            transfer.bytes -= old.len();
+            self.bytes -= old.len();
+        }
+        transfer.bytes += len;
+        self.bytes += len;
+        if transfer.chunks.len() < transfer.count as usize {
+            transfer.tags.push(msg.delivery_tag());
+            return None;
+        }
+        let transfer = self.transfers.remove(&id)?;
+        self.bytes -= transfer.bytes;
+        msg.set_data(transfer.chunks.into_values().flatten().collect());
+        Some((msg, transfer.tags))
+    }
+    /// Returns the delivery tags of the dropped chunks.
+    pub fn take_dropped(&mut self) -> Vec<u64> {
+        std::mem::take(&mut self.dropped)
+    }
+    /// Returns the duration until the oldest incomplete transfer expires.
+    pub fn next_expiry(&self) -> Option<Duration> {
+        self.transfers
+            .values()
+            .map(|t| self.timeout.checked_sub(t.started.elapsed()))
+            .min()
+            .map(Option::unwrap_or_default)
+    }
+    /// Drops the incomplete transfers which don't complete within the
+    /// timeout.
+    pub fn expire(&mut self) {
+        let timeout = self.timeout;
+        let expired: Vec<_> = self
+            .transfers
+            .iter()
+            .filter(|(_, t)| t.started.elapsed() >= timeout)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in &expired {
+            self.drop_transfer(id);
+        }
+    }
+    fn drop_oldest(&mut self, except: &str) -> bool {
+        let oldest = self
+            .transfers
+            .iter()
+            .filter(|(id, _)| id.as_str() != except)
+            .min_by_key(|(_, t)| t.started)
+            .map(|(id, _)| id.clone());
+        match oldest {
+            Some(id) => {
+                self.drop_transfer(&id);
+                true
+            }
+            None => false,
+        }
+    }
+    fn drop_transfer(&mut self, id: &str) {
+        if let Some(transfer) = self.transfers.remove(id) {
+            self.bytes -= transfer.bytes;
+            self.dropped.extend(transfer.tags);
+        }
+    }
+}
+
+/// Returns the transfer ID, the chunk index and the chunk count
+/// of the message.
+fn chunk(msg: &crate::Message) -> Option<(String, u32, u32)> {
+    let id = msg
+        .header_value(TRANSFER_ID_HEADER)
+        .and_then(crate::message::header_str)?;
+    let index = msg.header_value(CHUNK_INDEX_HEADER).and_then(header_u32)?;
+    let count = msg.header_value(CHUNK_COUNT_HEADER).and_then(header_u32)?;
+    Some((id.to_string(), index, count))
+}
+
+fn header_u32(value: &lapin::types::AMQPValue) -> Option<u32> {
+    match value {
+        lapin::types::AMQPValue::LongUInt(v) => Some(*v),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    fn messages(data: &[u8], size: usize, first_tag: u64) -> Vec<crate::Message> {
+        super::split(lapin::BasicProperties::default(), data.to_vec(), size)
+            .into_iter()
+            .enumerate()
+            .map(|(i, (props, data))| {
+                crate::message::test_message(first_tag + i as u64, "", "", props, &data)
+            })
+            .collect()
+    }
+    #[test]
+    fn push_in_order() {
+        struct Test {
+            name: &'static str,
+            data: &'static [u8],
+            size: usize,
+            want_tags: Vec<u64>,
+        }
+        let tests = [
+            Test {
+                name: "not chunked",
+                data: b"0123456789",
+                size: 10,
+                want_tags: vec![],
+            },
+            Test {
+                name: "two chunks",
+                data: b"0123456789",
+                size: 5,
+                want_tags: vec![1],
+            },
+            Test {
+                name: "four chunks",
+                data: b"0123456789",
+                size: 3,
+                want_tags: vec![1, 2, 3],
+            },
+        ];
+        for t in &tests {
+            let mut r = super::Reassembler::default();
+            let mut msgs = messages(t.data, t.size, 1);
+            let last = msgs.pop().unwrap();
+            for msg in msgs {
+                assert!(r.push(msg).is_none(), "{}", t.name);
+            }
+            let (got, tags) = r.push(last).unwrap();
+            assert_eq!(t.data, got.data(), "{}", t.name);
+            assert_eq!(t.want_tags, tags, "{}", t.name);
+            assert!(r.take_dropped().is_empty(), "{}", t.name);
+        }
+    }
+    #[test]
+    fn push_out_of_order() {
+        let mut r = super::Reassembler::default();
+        let mut msgs = messages(b"0123456789", 3, 1);
+        msgs.reverse();
+        let first = msgs.pop().unwrap();
+        for msg in msgs {
+            assert!(r.push(msg).is_none());
+        }
+        let (got, tags) = r.push(first).unwrap();
+        assert_eq!(b"0123456789", got.data());
+        assert_eq!(vec![4, 3, 2], tags);
+    }
+    #[test]
+    fn drop_over_memory_bound() {
+        let mut r = super::Reassembler::new(super::DEFAULT_TIMEOUT, 12);
+        let mut old = messages(b"0123456789", 5, 1);
+        let mut new = messages(b"abcdefghij", 5, 3);
+        assert!(r.push(old.remove(0)).is_none());
+        assert!(r.push(new.remove(0)).is_none());
+        assert!(r.take_dropped().is_empty());
+        let (got, tags) = r.push(new.remove(0)).unwrap();
+        assert_eq!(b"abcdefghij", got.data());
+        assert_eq!(vec![3], tags);
+        assert_eq!(vec![1], r.take_dropped());
+    }
+    #[test]
+    fn drop_too_large_transfer() {
+        let mut r = super::Reassembler::new(super::DEFAULT_TIMEOUT, 8);
+        let mut msgs = messages(b"0123456789", 5, 1);
+        assert!(r.push(msgs.remove(0)).is_none());
+        assert!(r.push(msgs.remove(0)).is_none());
+        assert_eq!(vec![1, 2], r.take_dropped());
+    }
+    #[test]
+    fn drop_expired() {
+        let mut r = super::Reassembler::new(Duration::from_millis(0), super::DEFAULT_MAX_BYTES);
+        let mut msgs = messages(b"0123456789", 5, 1);
+        assert!(r.push(msgs.remove(0)).is_none());
+        std::thread::sleep(Duration::from_millis(1));
+        assert!(r.push(msgs.remove(0)).is_none());
+        assert_eq!(vec![1], r.take_dropped());
+    }
+    #[test]
+    fn expire() {
+        let mut r = super::Reassembler::new(Duration::from_millis(1), super::DEFAULT_MAX_BYTES);
+        assert_eq!(None, r.next_expiry());
+        let mut msgs = messages(b"0123456789", 3, 1);
+        assert!(r.push(msgs.remove(0)).is_none());
+        assert!(r.push(msgs.remove(0)).is_none());
+        assert!(r.next_expiry().is_some());
+        std::thread::sleep(Duration::from_millis(2));
+        assert_eq!(Some(Duration::from_millis(0)), r.next_expiry());
+        r.expire();
+        assert_eq!(vec![1, 2], r.take_dropped());
+        assert_eq!(None, r.next_expiry());
+    }
+    #[test]
+    fn drop_inconsistent_count() {
+        let mut r = super::Reassembler::default();
+        let mut msgs = messages(b"0123456789", 5, 1);
+        let mut other = messages(b"0123456789", 3, 3);
+        let props = crate::message::with_header(
+            other[1].properties().clone(),
+            super::TRANSFER_ID_HEADER,
+            lapin::types::AMQPValue::LongString(
+                msgs[0]
+                    .header::<String>(super::TRANSFER_ID_HEADER)
+                    .unwrap()
+                    .into(),
+            ),
+        );
+        let other = crate::message::test_message(4, "", "", props, other.remove(1).data());
+        assert!(r.push(msgs.remove(0)).is_none());
+        assert!(r.push(other).is_none());
+        assert_eq!(vec![1, 4], r.take_dropped());
+        assert!(r.push(msgs.remove(0)).is_none());
+    }
+}
@@ -80,6 +80,17 @@ impl Connection {
         opts: QueueOptions,
     ) -> crate::Result<(lapin::Channel, lapin::Queue)> {
         let ch = self.0.create_channel().await.map_err(crate::Error::from)?;
+        let q = Self::declare(&ch, ex, queue, opts).await?;
+        Ok((ch, q))
+    }
+    /// declare declares a queue, as well as the exchange and the binding
+    /// to it, over the existing channel.
+    pub(crate) async fn declare(
+        ch: &lapin::Channel,
+        ex: &str,
+        queue: &str,
+        opts: QueueOptions,
+    ) -> crate::Result<lapin::Queue> {
         let q = ch
             .queue_declare(queue, opts.queue_opts, opts.queue_field)
             .await
@@ -87,7 +98,7 @@ impl Connection {
         if Self::is_default_exchange(ex) {
             // We don't need to bind to the exchange in case of the default
             // exchange.
-            return Ok((ch, q));
+            return Ok(q);
         }
         ch.exchange_declare(ex, opts.kind, opts.ex_opts, opts.ex_field)
             .await
@@ -106,7 +117,7 @@ impl Connection {
         )
         .await
         .map_err(crate::Error::from)?;
-        Ok((ch, q))
+        Ok(q)
     }
     fn is_default_exchange(name: &str) -> bool {
         name == crate::DEFAULT_EXCHANGE
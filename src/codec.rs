@@ -0,0 +1,741 @@
+// SPDX-License-Identifier: Apache-2.0 AND MIT
+//! `Codec` trait, `Dispatcher` and `Registry` structs and the codecs
+//!
+//! Each codec is available through the crate feature, `json`, `msgpack`,
+//! `cbor`, `bincode`, `protobuf` and `flatbuffer` respectively.
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// A trait to encode and decode the message payload of `T`.
+pub trait Codec<T> {
+    /// Returns the content type set to the encoded messages.
+    fn content_type(&self) -> &str;
+    /// Returns the message type, e.g. the protobuf message name, set to
+    /// the AMQP `type` property to dispatch the different types on one
+    /// queue.
+    fn message_type(&self) -> Option<&str> {
+        None
+    }
+    fn encode(&self, value: &T) -> crate::Result<Vec<u8>>;
+    fn decode(&self, data: &[u8]) -> crate::Result<T>;
+}
+
+/// Returns the properties with the content type and the message type
+/// of the codec set.
+pub(crate) fn properties<T, C: Codec<T>>(
+    codec: &C,
+    props: lapin::BasicProperties,
+) -> lapin::BasicProperties {
+    let props = props.with_content_type(codec.content_type().into());
+    match codec.message_type() {
+        Some(message_type) => props.with_type(message_type.into()),
+        None => props,
+    }
+}
+
+type Decoder<R> = Box<dyn Fn(&[u8]) -> crate::Result<R> + Send + Sync>;
+
+/// A message type dispatcher, which decodes the message with the codec
+/// registered for the message's type, [Message::kind], and maps
+/// the decoded value into `R`, e.g. the enum of all the message types
+/// of the queue.
+///
+/// # Examples
+///
+/// ```no_run
+/// use async_mq::codec::{Codec, Dispatcher};
+///
+/// enum Request {
+///     Hello(String),
+///     Goodbye(u64),
+/// }
+///
+/// fn dispatcher<C>(codec: C) -> Dispatcher<Request>
+/// where
+///     C: Codec<String> + Codec<u64> + Clone + Send + Sync + 'static,
+/// {
+///     let mut dispatcher = Dispatcher::new();
+///     dispatcher
+///         .register("hello", codec.clone(), Request::Hello)
+///         .register("goodbye", codec, Request::Goodbye);
+///     dispatcher
+/// }
+/// ```
+///
+/// [Message::kind]: ../message/struct.Message.html#method.kind
+pub struct Dispatcher<R> {
+    decoders: HashMap<String, Decoder<R>>,
+}
+
+impl<R> Default for Dispatcher<R> {
+    fn default() -> Self {
+        Self {
+            decoders: HashMap::new(),
+        }
+    }
+}
+
+impl<R> Dispatcher<R> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Register the codec of the `message_type`, and the function to map
+    /// the decoded value into `R`.
+    pub fn register<T, C, F>(&mut self, message_type: &str, codec: C, f: F) -> &mut Self
+    where
+        C: Codec<T> + Send + Sync + 'static,
+        F: Fn(T) -> R + Send + Sync + 'static,
+    {
+        let decoder = move |data: &[u8]| codec.decode(data).map(&f);
+        self.decoders
+            .insert(message_type.to_string(), Box::new(decoder));
+        self
+    }
+    /// Decodes the message with the codec of its message type.
+    pub fn dispatch(&self, msg: &crate::Message) -> crate::Result<R> {
+        let message_type = msg
+            .kind()
+            .ok_or_else(|| crate::Error::Codec(String::from("missing message type")))?;
+        match self.decoders.get(message_type) {
+            Some(decoder) => decoder(msg.data()),
+            None => Err(crate::Error::Codec(format!(
+                "unknown message type: {}",
+                message_type
+            ))),
+        }
+    }
+}
+
+/// A JSON [Codec], `application/json`.
+///
+/// [Codec]: trait.Codec.html
+#[cfg(feature = "json")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl<T> Codec<T> for Json
+where
+    T: serde::Serialize + serde::de::DeserializeOwned,
+{
+    fn content_type(&self) -> &str {
+        "application/json"
+    }
+    fn encode(&self, value: &T) -> crate::Result<Vec<u8>> {
+        serde_json::to_vec(value).map_err(|err| crate::Error::Codec(err.to_string()))
+    }
+    fn decode(&self, data: &[u8]) -> crate::Result<T> {
+        serde_json::from_slice(data).map_err(|err| crate::Error::Codec(err.to_string()))
+    }
+}
+
+/// A MessagePack [Codec], `application/msgpack`.
+///
+/// The structs are encoded as maps, so that the fields can be added
+/// without breaking the other side.
+///
+/// [Codec]: trait.Codec.html
+#[cfg(feature = "msgpack")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MsgPack;
+
+#[cfg(feature = "msgpack")]
+impl<T> Codec<T> for MsgPack
+where
+    T: serde::Serialize + serde::de::DeserializeOwned,
+{
+    fn content_type(&self) -> &str {
+        "application/msgpack"
+    }
+    fn encode(&self, value: &T) -> crate::Result<Vec<u8>> {
+        rmp_serde::to_vec_named(value).map_err(|err| crate::Error::Codec(err.to_string()))
+    }
+    fn decode(&self, data: &[u8]) -> crate::Result<T> {
+        rmp_serde::from_slice(data).map_err(|err| crate::Error::Codec(err.to_string()))
+    }
+}
+
+/// A CBOR [Codec], `application/cbor`.
+///
+/// [Codec]: trait.Codec.html
+#[cfg(feature = "cbor")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Cbor;
+
+#[cfg(feature = "cbor")]
+impl<T> Codec<T> for Cbor
+where
+    T: serde::Serialize + serde::de::DeserializeOwned,
+{
+    fn content_type(&self) -> &str {
+        "application/cbor"
+    }
+    fn encode(&self, value: &T) -> crate::Result<Vec<u8>> {
+        serde_cbor::to_vec(value).map_err(|err| crate::Error::Codec(err.to_string()))
+    }
+    fn decode(&self, data: &[u8]) -> crate::Result<T> {
+        serde_cbor::from_slice(data).map_err(|err| crate::Error::Codec(err.to_string()))
+    }
+}
+
+/// A bincode [Codec], `application/x-bincode`.
+///
+/// [Codec]: trait.Codec.html
+#[cfg(feature = "bincode")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl<T> Codec<T> for Bincode
+where
+    T: serde::Serialize + serde::de::DeserializeOwned,
+{
+    fn content_type(&self) -> &str {
+        "application/x-bincode"
+    }
+    fn encode(&self, value: &T) -> crate::Result<Vec<u8>> {
+        bincode::serialize(value).map_err(|err| crate::Error::Codec(err.to_string()))
+    }
+    fn decode(&self, data: &[u8]) -> crate::Result<T> {
+        bincode::deserialize(data).map_err(|err| crate::Error::Codec(err.to_string()))
+    }
+}
+
+/// A codec registry keyed by the content type, to decode the messages
+/// of the different content types on one queue and to reply in the
+/// requester's content type.
+///
+/// The first registered codec is the default one, used for the messages
+/// without the content type.
+pub struct Registry<T> {
+    codecs: HashMap<String, Arc<dyn Codec<T> + Send + Sync>>,
+    default: Option<String>,
+    unknown: crate::MessageError,
+}
+
+impl<T> Clone for Registry<T> {
+    fn clone(&self) -> Self {
+        Self {
+            codecs: self.codecs.clone(),
+            default: self.default.clone(),
+            unknown: self.unknown.clone(),
+        }
+    }
+}
+
+impl<T> Default for Registry<T> {
+    fn default() -> Self {
+        Self {
+            codecs: HashMap::new(),
+            default: None,
+            unknown: crate::MessageError::reject(),
+        }
+    }
+}
+
+impl<T> Registry<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Register the codec for its content type.
+    pub fn register<C>(&mut self, codec: C) -> &mut Self
+    where
+        C: Codec<T> + Send + Sync + 'static,
+    {
+        let content_type = essence(codec.content_type());
+        if self.default.is_none() {
+            self.default = Some(content_type.clone());
+        }
+        self.codecs.insert(content_type, Arc::new(codec));
+        self
+    }
+    /// Specify the action for the messages of the unknown content type.
+    ///
+    /// It's [MessageError::reject] by default, and the reason is set
+    /// unless the action carries one.
+    ///
+    /// [MessageError::reject]: ../message/enum.MessageError.html#method.reject
+    pub fn on_unknown(&mut self, action: crate::MessageError) -> &mut Self {
+        self.unknown = action;
+        self
+    }
+    /// Returns the codec of the content type, or the default codec
+    /// in case of `None`.
+    pub fn codec(&self, content_type: Option<&str>) -> Option<&(dyn Codec<T> + Send + Sync)> {
+        let content_type = match content_type {
+            Some(content_type) => essence(content_type),
+            None => self.default.clone()?,
+        };
+        self.codecs.get(&content_type).map(|codec| codec.as_ref())
+    }
+    /// Decodes the message with the codec of its content type.
+    ///
+    /// It returns the unknown content type action, or the reject action
+    /// in case of the decode failure.
+    pub fn decode(&self, msg: &crate::Message) -> Result<T, crate::MessageError> {
+        let codec = match self.codec(msg.content_type()) {
+            Some(codec) => codec,
+            None => {
+                let reason = format!(
+                    "unsupported content type: {}",
+                    msg.content_type().unwrap_or_default()
+                );
+                return Err(match self.unknown.reason() {
+                    Some(_) => self.unknown.clone(),
+                    None => self.unknown.clone().with_reason(&reason),
+                });
+            }
+        };
+        codec
+            .decode(msg.data())
+            .map_err(|err| crate::MessageError::reject().with_reason(&err.to_string()))
+    }
+    /// Returns the reply encoded in the requester's content type, or in
+    /// the default content type in case the requester's one is unknown.
+    pub fn reply(&self, req: &crate::Message, value: &T) -> crate::Result<crate::Reply> {
+        let codec = self
+            .codec(req.content_type())
+            .or_else(|| self.codec(None))
+            .ok_or_else(|| crate::Error::Codec(String::from("no codec registered")))?;
+        let mut reply =
+            crate::Reply::new(codec.encode(value)?).with_content_type(codec.content_type());
+        if let Some(message_type) = codec.message_type() {
+            reply = reply.with_kind(message_type);
+        }
+        Ok(reply)
+    }
+}
+
+/// Returns the lower cased content type without the parameters,
+/// e.g. `application/json` of `application/json; charset=utf-8`.
+fn essence(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+/// A protobuf [Codec], `application/x-protobuf`, of the [prost] messages.
+///
+/// [Codec]: trait.Codec.html
+/// [prost]: https://crates.io/crates/prost
+#[cfg(feature = "protobuf")]
+#[derive(Clone, Debug, Default)]
+pub struct Protobuf {
+    message_type: Option<String>,
+}
+
+#[cfg(feature = "protobuf")]
+impl Protobuf {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Specify the message type, e.g. the fully qualified protobuf
+    /// message name, to be dispatched by the [Dispatcher].
+    ///
+    /// [Dispatcher]: struct.Dispatcher.html
+    pub fn with_message_type(message_type: &str) -> Self {
+        Self {
+            message_type: Some(message_type.to_string()),
+        }
+    }
+}
+
+#[cfg(feature = "protobuf")]
+impl<T> Codec<T> for Protobuf
+where
+    T: prost::Message + Default,
+{
+    fn content_type(&self) -> &str {
+        "application/x-protobuf"
+    }
+    fn message_type(&self) -> Option<&str> {
+        self.message_type.as_deref()
+    }
+    fn encode(&self, value: &T) -> crate::Result<Vec<u8>> {
+        let mut buf = Vec::with_capacity(value.encoded_len());
+        value
+            .encode(&mut buf)
+            .map_err(|err| crate::Error::Codec(err.to_string()))?;
+        Ok(buf)
+    }
+    fn decode(&self, data: &[u8]) -> crate::Result<T> {
+        T::decode(data).map_err(|err| crate::Error::Codec(err.to_string()))
+    }
+}
+
+/// A trait to build and read the FlatBuffers table of the type, used by
+/// the [FlatBuffer] codec.
+///
+/// The FlatBuffers tables are read in place, so that the implementation
+/// copies the fields out of the buffer into the owned type.  The buffer
+/// is untrusted, so that it should be read through the verifying
+/// accessor, e.g. [flatbuffers::root] or the generated `root_as_*`
+/// function, rather than the unchecked one which panics on the malformed
+/// buffer.
+///
+/// [FlatBuffer]: struct.FlatBuffer.html
+/// [flatbuffers::root]: https://docs.rs/flatbuffers/latest/flatbuffers/fn.root.html
+#[cfg(feature = "flatbuffer")]
+pub trait FlatBufferMessage: Sized {
+    /// Builds the root table and returns its offset.
+    fn build<'a>(
+        &self,
+        builder: &mut flatbuffers::FlatBufferBuilder<'a>,
+    ) -> flatbuffers::WIPOffset<flatbuffers::UnionWIPOffset>;
+    /// Reads the root table of the finished buffer, or returns
+    /// [Error::Codec] in case the buffer fails the verification.
+    ///
+    /// [Error::Codec]: ../enum.Error.html#variant.Codec
+    fn read(data: &[u8]) -> crate::Result<Self>;
+}
+
+/// A FlatBuffers [Codec], `application/x-flatbuffers`, of the
+/// [FlatBufferMessage] types.
+///
+/// [Codec]: trait.Codec.html
+/// [FlatBufferMessage]: trait.FlatBufferMessage.html
+#[cfg(feature = "flatbuffer")]
+#[derive(Clone, Debug, Default)]
+pub struct FlatBuffer {
+    message_type: Option<String>,
+}
+
+#[cfg(feature = "flatbuffer")]
+impl FlatBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Specify the message type, e.g. the root table name, to be
+    /// dispatched by the [Dispatcher].
+    ///
+    /// [Dispatcher]: struct.Dispatcher.html
+    pub fn with_message_type(message_type: &str) -> Self {
+        Self {
+            message_type: Some(message_type.to_string()),
+        }
+    }
+}
+
+#[cfg(feature = "flatbuffer")]
+impl<T: FlatBufferMessage> Codec<T> for FlatBuffer {
+    fn content_type(&self) -> &str {
+        "application/x-flatbuffers"
+    }
+    fn message_type(&self) -> Option<&str> {
+        self.message_type.as_deref()
+    }
+    fn encode(&self, value: &T) -> crate::Result<Vec<u8>> {
+        let mut builder = flatbuffers::FlatBufferBuilder::new();
+        let root = value.build(&mut builder);
+        builder.finish(root, None);
+        Ok(builder.finished_data().to_vec())
+    }
+    fn decode(&self, data: &[u8]) -> crate::Result<T> {
+        T::read(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(any(
+        feature = "json",
+        feature = "msgpack",
+        feature = "cbor",
+        feature = "bincode"
+    ))]
+    type Value = (String, u32, Vec<u8>);
+    #[cfg(any(
+        feature = "json",
+        feature = "msgpack",
+        feature = "cbor",
+        feature = "bincode"
+    ))]
+    fn encode_then_decode<C: super::Codec<Value>>(codec: C, content_type: &str) {
+        let value = (String::from("name"), 42, vec![1, 2, 3]);
+        let data = codec.encode(&value).unwrap();
+        assert_eq!(value, codec.decode(&data).unwrap(), "{}", content_type);
+        assert_eq!(content_type, codec.content_type());
+        assert!(codec.decode(b"\xff\xff").is_err(), "{}", content_type);
+    }
+    /// A test codec of the little endian `u32` and the UTF-8 `String`.
+    #[derive(Clone)]
+    struct Plain;
+    impl super::Codec<u32> for Plain {
+        fn content_type(&self) -> &str {
+            "application/octet-stream"
+        }
+        fn encode(&self, value: &u32) -> crate::Result<Vec<u8>> {
+            Ok(value.to_le_bytes().to_vec())
+        }
+        fn decode(&self, data: &[u8]) -> crate::Result<u32> {
+            let mut buf = [0u8; 4];
+            if data.len() != buf.len() {
+                return Err(crate::Error::Codec(String::from("invalid length")));
+            }
+            buf.copy_from_slice(data);
+            Ok(u32::from_le_bytes(buf))
+        }
+    }
+    impl super::Codec<String> for Plain {
+        fn content_type(&self) -> &str {
+            "text/plain"
+        }
+        fn encode(&self, value: &String) -> crate::Result<Vec<u8>> {
+            Ok(value.as_bytes().to_vec())
+        }
+        fn decode(&self, data: &[u8]) -> crate::Result<String> {
+            String::from_utf8(data.to_vec()).map_err(|err| crate::Error::Codec(err.to_string()))
+        }
+    }
+    /// A test codec of the decimal `u32` text.
+    struct Decimal;
+    impl super::Codec<u32> for Decimal {
+        fn content_type(&self) -> &str {
+            "text/plain"
+        }
+        fn encode(&self, value: &u32) -> crate::Result<Vec<u8>> {
+            Ok(value.to_string().into_bytes())
+        }
+        fn decode(&self, data: &[u8]) -> crate::Result<u32> {
+            std::str::from_utf8(data)
+                .ok()
+                .and_then(|data| data.parse().ok())
+                .ok_or_else(|| crate::Error::Codec(String::from("invalid decimal")))
+        }
+    }
+    fn message(content_type: Option<&str>, data: &[u8]) -> crate::Message {
+        let mut props = lapin::BasicProperties::default();
+        if let Some(content_type) = content_type {
+            props = props.with_content_type(content_type.into());
+        }
+        crate::message::test_message(1, "", "", props, data)
+    }
+    #[test]
+    fn registry_decode() {
+        struct Test {
+            name: &'static str,
+            content_type: Option<&'static str>,
+            data: &'static [u8],
+            want: Result<u32, crate::MessageError>,
+        }
+        let tests = [
+            Test {
+                name: "default content type",
+                content_type: None,
+                data: &[2, 0, 0, 0],
+                want: Ok(2),
+            },
+            Test {
+                name: "octet stream",
+                content_type: Some("application/octet-stream"),
+                data: &[2, 0, 0, 0],
+                want: Ok(2),
+            },
+            Test {
+                name: "text with parameter",
+                content_type: Some("Text/Plain; charset=utf-8"),
+                data: b"2",
+                want: Ok(2),
+            },
+            Test {
+                name: "invalid text",
+                content_type: Some("text/plain"),
+                data: b"two",
+                want: Err(crate::MessageError::reject().with_reason("codec error: invalid decimal")),
+            },
+            Test {
+                name: "unknown content type",
+                content_type: Some("application/json"),
+                data: b"2",
+                want: Err(crate::MessageError::nack()
+                    .with_requeue(true)
+                    .with_reason("unsupported content type: application/json")),
+            },
+        ];
+        let mut registry = super::Registry::new();
+        registry
+            .register(Plain)
+            .register(Decimal)
+            .on_unknown(crate::MessageError::nack().with_requeue(true));
+        for t in &tests {
+            let got = registry.decode(&message(t.content_type, t.data));
+            assert_eq!(t.want, got, "{}", t.name);
+        }
+    }
+    #[test]
+    fn registry_reply() {
+        struct Test {
+            name: &'static str,
+            content_type: Option<&'static str>,
+            want_content_type: &'static str,
+            want: &'static [u8],
+        }
+        let tests = [
+            Test {
+                name: "requester's content type",
+                content_type: Some("text/plain"),
+                want_content_type: "text/plain",
+                want: b"3",
+            },
+            Test {
+                name: "default content type",
+                content_type: None,
+                want_content_type: "application/octet-stream",
+                want: &[3, 0, 0, 0],
+            },
+            Test {
+                name: "unknown content type",
+                content_type: Some("application/json"),
+                want_content_type: "application/octet-stream",
+                want: &[3, 0, 0, 0],
+            },
+        ];
+        let mut registry = super::Registry::new();
+        registry.register(Plain).register(Decimal);
+        for t in &tests {
+            let reply = registry.reply(&message(t.content_type, b""), &3).unwrap();
+            assert_eq!(t.want, reply.data(), "{}", t.name);
+            let props = reply.properties(lapin::BasicProperties::default());
+            assert_eq!(
+                &Some(t.want_content_type.into()),
+                props.content_type(),
+                "{}",
+                t.name
+            );
+        }
+    }
+    #[derive(Debug, PartialEq)]
+    enum Request {
+        Count(u32),
+        Name(String),
+    }
+    #[test]
+    fn dispatch() {
+        struct Test {
+            name: &'static str,
+            message_type: Option<&'static str>,
+            data: &'static [u8],
+            want: Option<Request>,
+        }
+        let tests = [
+            Test {
+                name: "count",
+                message_type: Some("count"),
+                data: &[1, 0, 0, 0],
+                want: Some(Request::Count(1)),
+            },
+            Test {
+                name: "name",
+                message_type: Some("name"),
+                data: b"mq",
+                want: Some(Request::Name(String::from("mq"))),
+            },
+            Test {
+                name: "invalid count",
+                message_type: Some("count"),
+                data: b"mq",
+                want: None,
+            },
+            Test {
+                name: "unknown message type",
+                message_type: Some("other"),
+                data: b"mq",
+                want: None,
+            },
+            Test {
+                name: "missing message type",
+                message_type: None,
+                data: b"mq",
+                want: None,
+            },
+        ];
+        let mut dispatcher = super::Dispatcher::new();
+        dispatcher
+            .register("count", Plain, Request::Count)
+            .register("name", Plain, Request::Name);
+        for t in &tests {
+            let mut props = lapin::BasicProperties::default();
+            if let Some(message_type) = t.message_type {
+                props = props.with_type(message_type.into());
+            }
+            let msg = crate::message::test_message(1, "", "", props, t.data);
+            let got = dispatcher.dispatch(&msg).ok();
+            assert_eq!(t.want, got, "{}", t.name);
+        }
+    }
+    #[test]
+    #[cfg(feature = "protobuf")]
+    fn protobuf() {
+        use super::Codec;
+        let codec = super::Protobuf::with_message_type("google.protobuf.StringValue");
+        let value = String::from("name");
+        let data = codec.encode(&value).unwrap();
+        assert_eq!(value, Codec::<String>::decode(&codec, &data).unwrap());
+        assert_eq!(
+            Some("google.protobuf.StringValue"),
+            Codec::<String>::message_type(&codec)
+        );
+        let props = super::properties::<String, _>(&codec, lapin::BasicProperties::default());
+        assert_eq!(&Some("google.protobuf.StringValue".into()), props.kind());
+        assert!(Codec::<String>::decode(&codec, b"\xff").is_err());
+    }
+    /// A test FlatBuffers message of the root string.
+    #[cfg(feature = "flatbuffer")]
+    #[derive(Debug, PartialEq)]
+    struct Name(String);
+    #[cfg(feature = "flatbuffer")]
+    impl super::FlatBufferMessage for Name {
+        fn build<'a>(
+            &self,
+            builder: &mut flatbuffers::FlatBufferBuilder<'a>,
+        ) -> flatbuffers::WIPOffset<flatbuffers::UnionWIPOffset> {
+            builder.create_string(&self.0).as_union_value()
+        }
+        fn read(data: &[u8]) -> crate::Result<Self> {
+            flatbuffers::root::<&str>(data)
+                .map(|name| Name(name.to_string()))
+                .map_err(|err| crate::Error::Codec(err.to_string()))
+        }
+    }
+    #[test]
+    #[cfg(feature = "flatbuffer")]
+    fn flatbuffer() {
+        use super::Codec;
+        let codec = super::FlatBuffer::with_message_type("Name");
+        let value = Name(String::from("name"));
+        let data = codec.encode(&value).unwrap();
+        assert_eq!(value, Codec::<Name>::decode(&codec, &data).unwrap());
+        assert_eq!(
+            "application/x-flatbuffers",
+            Codec::<Name>::content_type(&codec)
+        );
+        let props = super::properties::<Name, _>(&codec, lapin::BasicProperties::default());
+        assert_eq!(&Some("Name".into()), props.kind());
+        assert!(Codec::<Name>::decode(&codec, b"\xff").is_err());
+        assert!(Codec::<Name>::decode(&codec, &data[..data.len() - 4]).is_err());
+        assert!(Codec::<Name>::decode(&codec, b"\xff\xff\xff\x7f").is_err());
+    }
+    #[test]
+    #[cfg(feature = "json")]
+    fn json() {
+        encode_then_decode(super::Json, "application/json");
+    }
+    #[test]
+    #[cfg(feature = "msgpack")]
+    fn msgpack() {
+        encode_then_decode(super::MsgPack, "application/msgpack");
+    }
+    #[test]
+    #[cfg(feature = "cbor")]
+    fn cbor() {
+        encode_then_decode(super::Cbor, "application/cbor");
+    }
+    #[test]
+    #[cfg(feature = "bincode")]
+    fn bincode() {
+        encode_then_decode(super::Bincode, "application/x-bincode");
+    }
+}
@@ -1,8 +1,14 @@
 // SPDX-License-Identifier: Apache-2.0 AND MIT
 //! `ConsumerBuilder` and `Consumer` structs
-use futures::stream::{Stream, StreamExt};
+use async_trait::async_trait;
+use futures::future::{self, BoxFuture, Either, Future};
+use futures::stream::{FuturesUnordered, Stream, StreamExt};
+use std::collections::{BTreeMap, BTreeSet};
 use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
 use std::task::{Context, Poll};
+use std::time::Duration;
 
 /// A [non-consuming] [Consumer] builder.
 ///
@@ -23,7 +29,23 @@ pub struct ConsumerBuilder {
     rx_opts: lapin::options::BasicConsumeOptions,
     ack_opts: lapin::options::BasicAckOptions,
     rej_opts: lapin::options::BasicRejectOptions,
-    processor: Box<dyn crate::MessageProcess + Send + Sync>,
+    qos_opts: lapin::options::BasicQosOptions,
+    peeker: Arc<dyn crate::MessagePeek + Send + Sync>,
+    processor: Arc<dyn crate::MessageProcess + Send + Sync>,
+    layers: Vec<Arc<dyn crate::middleware::Middleware + Send + Sync>>,
+    #[cfg(feature = "encryption")]
+    encryption: Option<crate::crypto::Encryption>,
+    reassemble: Option<(Duration, usize)>,
+    prefetch: Option<u16>,
+    concurrency: usize,
+    retry: Option<crate::retry::RetryPolicy>,
+    dead_letter: Option<(String, String)>,
+    copy_content_type: bool,
+    upcasters: Option<crate::schema::Upcasters>,
+    #[cfg(feature = "json-schema")]
+    validator: Option<crate::validate::Validator>,
+    routing_keys: BTreeSet<String>,
+    bindings: RoutingKeys,
 }
 
 impl ConsumerBuilder {
@@ -42,7 +64,23 @@ impl ConsumerBuilder {
             rx_opts: lapin::options::BasicConsumeOptions::default(),
             ack_opts: lapin::options::BasicAckOptions::default(),
             rej_opts: lapin::options::BasicRejectOptions::default(),
-            processor: Box::new(crate::message::EchoProcessor {}),
+            qos_opts: lapin::options::BasicQosOptions::default(),
+            peeker: Arc::new(crate::message::NoopPeeker {}),
+            processor: Arc::new(crate::message::EchoProcessor {}),
+            layers: Vec::new(),
+            #[cfg(feature = "encryption")]
+            encryption: None,
+            reassemble: None,
+            prefetch: None,
+            concurrency: 1,
+            retry: None,
+            dead_letter: None,
+            copy_content_type: false,
+            upcasters: None,
+            #[cfg(feature = "json-schema")]
+            validator: None,
+            routing_keys: BTreeSet::new(),
+            bindings: RoutingKeys::default(),
         }
     }
     /// Specify the exchange name.
@@ -55,27 +93,260 @@ impl ConsumerBuilder {
         self.queue = queue.to_string();
         self
     }
+    /// Specify the number of unacknowledged messages the broker delivers
+    /// to the consumer, through the `basic.qos` method.
+    ///
+    /// It's unlimited by default.  The chunks are held unacknowledged
+    /// until the transfer is reassembled, so that the count should be
+    /// larger than the number of chunks of the largest message when
+    /// used together with [reassemble], otherwise the broker stops the
+    /// delivery and the transfer is dropped on the timeout.
+    ///
+    /// [reassemble]: #method.reassemble
+    pub fn prefetch(&mut self, count: u16) -> &mut Self {
+        self.prefetch = Some(count);
+        self
+    }
+    /// Specify the number of messages [Consumer::run] processes
+    /// concurrently.
+    ///
+    /// It's one by default.  The [prefetch] count should be equal or
+    /// larger than the concurrency to keep all the processors busy.
+    ///
+    /// [Consumer::run]: struct.Consumer.html#method.run
+    /// [prefetch]: #method.prefetch
+    pub fn concurrency(&mut self, n: usize) -> &mut Self {
+        self.concurrency = n.max(1);
+        self
+    }
+    /// Use the provided [MessagePeek] trait object.
+    ///
+    /// The peeker is called before the [MessageProcess] trait object,
+    /// e.g. [Verifier] to reject unsigned messages.
+    ///
+    /// [MessagePeek]: ../message/trait.MessagePeek.html
+    /// [MessageProcess]: ../message/trait.MessageProcess.html
+    /// [Verifier]: ../sign/struct.Verifier.html
+    pub fn with_peeker(&mut self, peeker: Arc<dyn crate::MessagePeek + Send + Sync>) -> &mut Self {
+        self.peeker = peeker;
+        self
+    }
     /// Use the provided [MessageProcess] trait object.
     ///
     /// [MessageProcess]: ../message/trait.MessageProcess.html
     pub fn with_processor(
         &mut self,
-        processor: Box<dyn crate::MessageProcess + Send + Sync>,
+        processor: Arc<dyn crate::MessageProcess + Send + Sync>,
     ) -> &mut Self {
         self.processor = processor;
         self
     }
+    /// Wrap the [MessageProcess] with the provided [Middleware].
+    ///
+    /// The first layered middleware is the outermost one.  The
+    /// [MessagePeek] isn't wrapped, so that the hooks are called once for
+    /// each message.
+    ///
+    /// [MessagePeek]: ../message/trait.MessagePeek.html
+    /// [MessageProcess]: ../message/trait.MessageProcess.html
+    /// [Middleware]: ../middleware/trait.Middleware.html
+    pub fn layer<M>(&mut self, middleware: M) -> &mut Self
+    where
+        M: crate::middleware::Middleware + Send + Sync + 'static,
+    {
+        self.layers.push(Arc::new(middleware));
+        self
+    }
+    /// Decrypt the messages and encrypt the replies with the provided
+    /// [Encryption].
+    ///
+    /// [Encryption]: ../crypto/struct.Encryption.html
+    #[cfg(feature = "encryption")]
+    pub fn with_encryption(&mut self, encryption: crate::crypto::Encryption) -> &mut Self {
+        self.encryption = Some(encryption);
+        self
+    }
+    /// Reassemble the chunked messages before processing them.
+    ///
+    /// The incomplete transfers are dropped, and its chunks are rejected,
+    /// when those are not completed within the `timeout` or when those
+    /// exceed the `max_bytes` memory bound.
+    ///
+    /// The chunks are acknowledged only once the message is processed,
+    /// so that the [prefetch] count, if any, should be larger than the
+    /// number of chunks of the largest message, i.e. its size divided by
+    /// the [Producer]'s chunk size, or the transfer never completes.
+    ///
+    /// [prefetch]: #method.prefetch
+    /// [Producer]: ../produce/struct.ProducerBuilder.html#method.chunk_size
+    pub fn reassemble(&mut self, timeout: Duration, max_bytes: usize) -> &mut Self {
+        self.reassemble = Some((timeout, max_bytes));
+        self
+    }
+    /// Retry the messages failed to be processed with the retriable error,
+    /// the one with the `requeue` flag, with the provided [RetryPolicy]
+    /// instead of requeuing those.
+    ///
+    /// The error reply, if any, is sent on each failure.  The retry queues
+    /// and the parking queue are declared durable alongside the consumer's
+    /// queue, without the `amq.` prefix of the server-named queue.
+    /// The chunked messages are not retried.
+    ///
+    /// [RetryPolicy]: ../retry/struct.RetryPolicy.html
+    pub fn retry(&mut self, policy: crate::retry::RetryPolicy) -> &mut Self {
+        self.retry = Some(policy);
+        self
+    }
+    /// Upcast the message payloads of the older schema versions with the
+    /// provided [Upcasters] before processing them.
+    ///
+    /// [Upcasters]: ../schema/struct.Upcasters.html
+    pub fn upcast(&mut self, upcasters: crate::schema::Upcasters) -> &mut Self {
+        self.upcasters = Some(upcasters);
+        self
+    }
+    /// Validate the decrypted and upcasted message payloads with the
+    /// provided [Validator] before processing them.
+    ///
+    /// [Validator]: ../validate/struct.Validator.html
+    #[cfg(feature = "json-schema")]
+    pub fn validate(&mut self, validator: crate::validate::Validator) -> &mut Self {
+        self.validator = Some(validator);
+        self
+    }
+    /// Copy the request's content type and encoding to the replies.
+    ///
+    /// The correlation ID is always copied.
+    pub fn copy_content_type(&mut self, copy: bool) -> &mut Self {
+        self.copy_content_type = copy;
+        self
+    }
+    /// Dead-letter the rejected messages to the `exchange`, and to the
+    /// `queue` bound to it.
+    ///
+    /// Both the dead-letter exchange and the dead-letter queue are declared
+    /// durable alongside the consumer's queue, over the consumer's channel,
+    /// and the messages are routed with the dead-letter queue name.  The
+    /// dead-letter queue is never exclusive nor auto-deleted, as it outlives
+    /// the consumer.  The dead-letter queue is bound directly in
+    /// case of the default exchange, `""`.
+    ///
+    /// The messages rejected without requeue, with the reason, by the
+    /// processor or by the consumer stages are dead-lettered by the
+    /// consumer itself, with the reason in the [ERROR_REASON_HEADER]
+    /// header.  The nacked messages, as well as the messages failed by the
+    /// [MessagePeek], are dead-lettered by the broker instead.
+    ///
+    /// [ERROR_REASON_HEADER]: ../message/constant.ERROR_REASON_HEADER.html
+    /// [MessagePeek]: ../message/trait.MessagePeek.html
+    pub fn dead_letter(&mut self, exchange: &str, queue: &str) -> &mut Self {
+        self.dead_letter = Some((exchange.to_string(), queue.to_string()));
+        self
+    }
+    /// Bind the queue with the additional `routing_key`, on top of the
+    /// queue name.
+    ///
+    /// Those are the initial routing keys of each exchange and queue pair.
+    /// The [Bindings] handles of the consumers built by this builder, or
+    /// by its clones, track the runtime bindings of the pair, so that
+    /// those are restored when the consumer of the same pair is rebuilt,
+    /// e.g. after the reconnect.  The broker refuses the bindings to the
+    /// default exchange, `""`.
+    ///
+    /// [Bindings]: struct.Bindings.html
+    pub fn bind(&mut self, routing_key: &str) -> &mut Self {
+        self.routing_keys.insert(routing_key.to_string());
+        self
+    }
+    /// Restore the runtime bindings tracked by the [Bindings] handle,
+    /// e.g. of the consumer built over the previous connection, instead
+    /// of the initial routing keys.
+    ///
+    /// The bindings are tracked by the queue name given to the builder,
+    /// rather than the broker assigned one, so that those are restored
+    /// to the server-named queue declared on the new connection, too.
+    ///
+    /// [Bindings]: struct.Bindings.html
+    pub fn with_bindings(&mut self, bindings: Bindings) -> &mut Self {
+        self.bindings = bindings.routing_keys;
+        self
+    }
+    /// Build the [MultiConsumer], which consumes the `queues` over a
+    /// single channel, with the weight of each queue.
+    ///
+    /// Each queue is declared and bound as the [build] method does, with
+    /// the dead-letter queue arguments and the [bind] routing keys, except
+    /// the retry queues, as the messages are yielded instead of being
+    /// processed.  The rejected messages are dead-lettered by the broker,
+    /// without the error reason.
+    ///
+    /// [MultiConsumer]: ../multi/struct.MultiConsumer.html
+    /// [build]: #method.build
+    /// [bind]: #method.bind
+    pub async fn build_multi(
+        &self,
+        queues: &[(&str, u32)],
+    ) -> crate::Result<crate::multi::MultiConsumer> {
+        let ch = self.conn.channel().await?;
+        if let Some(prefetch) = self.prefetch {
+            ch.basic_qos(prefetch, self.qos_opts.clone())
+                .await
+                .map_err(crate::Error::from)?;
+        }
+        let queue_field = self.queue_field(&ch).await?;
+        let mut consumes = Vec::with_capacity(queues.len());
+        for (queue, weight) in queues {
+            let opts = crate::client::QueueOptions {
+                kind: self.kind.clone(),
+                ex_opts: self.ex_opts.clone(),
+                ex_field: self.field_table.clone(),
+                queue_opts: self.queue_opts.clone(),
+                queue_field: queue_field.clone(),
+                bind_opts: self.bind_opts.clone(),
+                bind_field: self.field_table.clone(),
+            };
+            let q = crate::client::Connection::declare(&ch, &self.ex, queue, opts).await?;
+            self.bindings(&ch, queue, q.name().as_str()).await?;
+            let consume = ch
+                .clone()
+                .basic_consume(
+                    q.name().as_str(),
+                    &format!("consumer-{}", q.name().as_str()),
+                    self.rx_opts.clone(),
+                    self.field_table.clone(),
+                )
+                .await
+                .map_err(crate::Error::from)?;
+            consumes.push((q.name().as_str().to_string(), consume, *weight));
+        }
+        Ok(crate::multi::MultiConsumer::new(
+            ch,
+            consumes,
+            !self.rx_opts.no_ack,
+        ))
+    }
     pub async fn build(&self) -> crate::Result<Consumer> {
+        let ch = self.conn.channel().await?;
+        let queue_field = self.queue_field(&ch).await?;
         let opts = crate::client::QueueOptions {
             kind: self.kind.clone(),
             ex_opts: self.ex_opts.clone(),
             ex_field: self.field_table.clone(),
             queue_opts: self.queue_opts.clone(),
-            queue_field: self.field_table.clone(),
+            queue_field,
             bind_opts: self.bind_opts.clone(),
             bind_field: self.field_table.clone(),
         };
-        let (ch, q) = self.conn.queue(&self.ex, &self.queue, opts).await?;
+        let q = crate::client::Connection::declare(&ch, &self.ex, &self.queue, opts).await?;
+        let bindings = self.bindings(&ch, &self.queue, q.name().as_str()).await?;
+        if let Some(policy) = &self.retry {
+            policy.declare(&ch, q.name().as_str()).await?;
+        }
+        if let Some(prefetch) = self.prefetch {
+            ch.basic_qos(prefetch, self.qos_opts.clone())
+                .await
+                .map_err(crate::Error::from)?;
+        }
         let consume = ch
             .clone()
             .basic_consume(
@@ -87,96 +358,984 @@ impl ConsumerBuilder {
             .await
             .map_err(crate::Error::from)?;
         Ok(Consumer {
-            ch,
             consume,
-            ex: self.ex.clone(),
-            tx_props: self.tx_props.clone(),
-            tx_opts: self.tx_opts.clone(),
-            ack_opts: self.ack_opts.clone(),
-            rej_opts: self.rej_opts.clone(),
-            processor: self.processor.clone(),
+            handle: ConsumerHandle::new(
+                self.peeker.clone(),
+                self.processor.clone(),
+                self.layers.clone(),
+            ),
+            bindings,
+            reassembler: self
+                .reassemble
+                .map(|(timeout, max_bytes)| crate::chunk::Reassembler::new(timeout, max_bytes)),
+            concurrency: self.concurrency,
+            ack: !self.rx_opts.no_ack,
+            responder: Responder {
+                ch,
+                ex: self.ex.clone(),
+                tx_props: self.tx_props.clone(),
+                tx_opts: self.tx_opts.clone(),
+                unsettled: match self.reassemble {
+                    Some(_) => None,
+                    None => Some(Arc::new(AtomicUsize::new(0))),
+                },
+                copy_content_type: self.copy_content_type,
+                upcasters: self.upcasters.clone(),
+                #[cfg(feature = "json-schema")]
+                validator: self.validator.clone(),
+                dead_letter: self.dead_letter.clone(),
+                ack_opts: self.ack_opts.clone(),
+                rej_opts: self.rej_opts.clone(),
+                #[cfg(feature = "encryption")]
+                encryption: self.encryption.clone(),
+                retry: self
+                    .retry
+                    .clone()
+                    .map(|policy| (policy, q.name().as_str().to_string())),
+            },
         })
     }
+    /// Declares the dead-letter exchange and queue, if any, and returns the
+    /// queue arguments to dead-letter the rejected messages to those.
+    async fn queue_field(&self, ch: &lapin::Channel) -> crate::Result<lapin::types::FieldTable> {
+        match &self.dead_letter {
+            Some((ex, queue)) => {
+                let opts = crate::client::QueueOptions {
+                    kind: lapin::ExchangeKind::Direct,
+                    ex_opts: lapin::options::ExchangeDeclareOptions {
+                        durable: true,
+                        ..Default::default()
+                    },
+                    ex_field: lapin::types::FieldTable::default(),
+                    queue_opts: lapin::options::QueueDeclareOptions {
+                        durable: true,
+                        ..Default::default()
+                    },
+                    queue_field: lapin::types::FieldTable::default(),
+                    bind_opts: self.bind_opts.clone(),
+                    bind_field: lapin::types::FieldTable::default(),
+                };
+                crate::client::Connection::declare(ch, ex, queue, opts).await?;
+                Ok(crate::dlq::arguments(self.field_table.clone(), ex, queue))
+            }
+            None => Ok(self.field_table.clone()),
+        }
+    }
+    /// Returns the [Bindings] of the declared `name` queue, requested as
+    /// the `queue`, after binding its routing keys.
+    ///
+    /// [Bindings]: struct.Bindings.html
+    async fn bindings(
+        &self,
+        ch: &lapin::Channel,
+        queue: &str,
+        name: &str,
+    ) -> crate::Result<Bindings> {
+        let bindings = Bindings {
+            ch: ch.clone(),
+            ex: self.ex.clone(),
+            queue: name.to_string(),
+            key: queue.to_string(),
+            bind_opts: self.bind_opts.clone(),
+            bind_field: self.field_table.clone(),
+            routing_keys: self.bindings.clone(),
+        };
+        bindings.restore(&self.routing_keys).await?;
+        Ok(bindings)
+    }
 }
 
 /// A zero-cost [lapin::Consumer] abstruction type.
 ///
 /// [lapin::Consumer]: https://docs.rs/lapin/latest/lapin/struct.Consumer.html
 pub struct Consumer {
-    ch: lapin::Channel,
     consume: lapin::Consumer,
-    ex: String,
-    tx_props: lapin::BasicProperties,
-    tx_opts: lapin::options::BasicPublishOptions,
-    ack_opts: lapin::options::BasicAckOptions,
-    rej_opts: lapin::options::BasicRejectOptions,
-    processor: Box<dyn crate::MessageProcess + Send + Sync>,
+    handle: ConsumerHandle,
+    bindings: Bindings,
+    reassembler: Option<crate::chunk::Reassembler>,
+    concurrency: usize,
+    ack: bool,
+    responder: Responder,
 }
 
 impl Consumer {
-    /// Use the provided [MessageProcess] trait object.
+    /// Use the provided [MessagePeek] trait object.
+    ///
+    /// [MessagePeek]: ../message/trait.MessagePeek.html
+    pub fn with_peeker(&mut self, peeker: Arc<dyn crate::MessagePeek + Send + Sync>) -> &mut Self {
+        self.handle.swap_peeker(peeker);
+        self
+    }
+    /// Use the provided [MessageProcess] trait object, wrapped with the
+    /// builder's [Middleware] layers.
     ///
     /// [MessageProcess]: ../message/trait.MessageProcess.html
+    /// [Middleware]: ../middleware/trait.Middleware.html
     pub fn with_processor(
         &mut self,
-        processor: Box<dyn crate::MessageProcess + Send + Sync>,
+        processor: Arc<dyn crate::MessageProcess + Send + Sync>,
     ) -> &mut Self {
-        self.processor = processor;
+        self.handle.swap_processor(processor);
         self
     }
+    /// Returns the [ConsumerHandle] to replace the trait objects while
+    /// the consumer is running.
+    ///
+    /// [ConsumerHandle]: struct.ConsumerHandle.html
+    pub fn handle(&self) -> ConsumerHandle {
+        self.handle.clone()
+    }
+    /// Returns the channel the messages are delivered over.
+    #[inline]
+    pub(crate) fn channel(&self) -> &lapin::Channel {
+        &self.responder.ch
+    }
+    /// Returns true in case of the messages are consumed with the `no_ack`
+    /// option.
+    #[inline]
+    pub(crate) fn no_ack(&self) -> bool {
+        !self.ack
+    }
+    /// Returns true in case of the chunked messages are reassembled.
+    #[inline]
+    pub(crate) fn reassembles(&self) -> bool {
+        self.reassembler.is_some()
+    }
+    /// Returns the [Bindings] handle to bind and unbind the routing keys
+    /// while the consumer is running.
+    ///
+    /// [Bindings]: struct.Bindings.html
+    pub fn bindings(&self) -> Bindings {
+        self.bindings.clone()
+    }
+    /// Process the messages, up to the [concurrency] messages at a time.
+    ///
+    /// Each message is acknowledged individually, so that the messages
+    /// completed out of order are acknowledged correctly.  The in-flight
+    /// messages share the single [MessageProcess] trait object, rather
+    /// than a copy of it each, so that its state is kept across those.
+    ///
+    /// [concurrency]: struct.ConsumerBuilder.html#method.concurrency
+    /// [MessageProcess]: ../message/trait.MessageProcess.html
     pub async fn run(&mut self) -> crate::Result<()> {
-        while let Some(msg) = self.consume.next().await {
-            match msg {
-                Ok(msg) => {
-                    let req = &crate::Message::new(msg);
-                    match self.processor.process(req).await {
-                        Ok(resp) => self.response(req, &resp).await?,
-                        Err(_err) => self.reject(req).await?,
+        Inflight::new(self.concurrency).run(self).await
+    }
+    pub async fn response(&mut self, req: &crate::Message, resp: &[u8]) -> crate::Result<()> {
+        self.reply(req, crate::Reply::new(resp.to_vec())).await
+    }
+    /// Send the [Reply], with its properties and headers, and acknowledge
+    /// the request.
+    ///
+    /// [Reply]: ../message/struct.Reply.html
+    pub async fn reply(&mut self, req: &crate::Message, reply: crate::Reply) -> crate::Result<()> {
+        self.responder.response(req, reply).await
+    }
+    pub async fn reject(&mut self, req: &crate::Message) -> crate::Result<()> {
+        self.responder.reject(req).await
+    }
+    /// Settle the request with the [MessageError] action, sending the
+    /// error reply if any.
+    ///
+    /// [MessageError]: ../message/enum.MessageError.html
+    pub async fn error(
+        &mut self,
+        req: &crate::Message,
+        err: crate::MessageError,
+    ) -> crate::Result<()> {
+        self.responder.fail(req, err, None).await
+    }
+    /// Returns the next message prepared the same way as the [run] method
+    /// does, i.e. peeked, reassembled, decrypted, upcasted and validated,
+    /// while the messages failed on the way are settled with the error
+    /// action.  It returns `None` once the consumer is cancelled.
+    ///
+    /// The message carries the [Acker] handle unless those are consumed
+    /// with the `no_ack` option.
+    ///
+    /// [run]: #method.run
+    /// [Acker]: ../ack/struct.Acker.html
+    pub(crate) async fn prepared(&mut self) -> Option<crate::Result<crate::Message>> {
+        self.responder.unsettled = None;
+        loop {
+            let req = match self.recv().await {
+                Some(Some(Ok(msg))) => crate::Message::new(msg),
+                Some(Some(Err(err))) => return Some(Err(crate::Error::from(err))),
+                Some(None) => return None,
+                None => match self.expire().await {
+                    Ok(()) => continue,
+                    Err(err) => return Some(Err(err)),
+                },
+            };
+            let peeker = self.handle.handlers().peeker;
+            let mut req = match self.admit(&peeker, req).await {
+                Ok(Some(req)) => req,
+                Ok(None) => continue,
+                Err(err) => return Some(Err(err)),
+            };
+            let raw = self.responder.raw(&req);
+            match self.responder.prepare(&mut req, raw).await {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(err) => return Some(Err(err)),
+            }
+            if self.ack {
+                req.set_acker(self.responder.ch.clone());
+            }
+            return Some(Ok(req));
+        }
+    }
+    /// Peeks and reassembles the message, and returns `None` in case the
+    /// message is settled on the peek failure or it waits for the other
+    /// chunks.
+    ///
+    /// The message failed to be peeked, e.g. the unsigned one, is settled
+    /// without the error reply, not to reply to its unverified `reply_to`.
+    async fn admit(
+        &mut self,
+        peeker: &Arc<dyn crate::MessagePeek + Send + Sync>,
+        req: crate::Message,
+    ) -> crate::Result<Option<crate::Message>> {
+        if let Err(err) = peeker.peek(&req).await {
+            let raw = self.responder.raw(&req);
+            self.responder.discard(&req, err, raw).await?;
+            return Ok(None);
+        }
+        self.reassemble(req).await
+    }
+    /// Returns the next delivery, or `None` once the oldest incomplete
+    /// chunked transfer expires.
+    async fn recv(&mut self) -> Option<Option<<lapin::Consumer as Stream>::Item>> {
+        let expiry = self.reassembler.as_ref().and_then(|r| r.next_expiry());
+        match expiry {
+            Some(expiry) => {
+                match future::select(self.consume.next(), futures_timer::Delay::new(expiry)).await {
+                    Either::Left((msg, _)) => Some(msg),
+                    Either::Right(_) => None,
+                }
+            }
+            None => Some(self.consume.next().await),
+        }
+    }
+    /// Rejects the chunks of the expired transfers.
+    async fn expire(&mut self) -> crate::Result<()> {
+        if let Some(reassembler) = &mut self.reassembler {
+            reassembler.expire();
+            let dropped = reassembler.take_dropped();
+            self.responder.reject_tags(dropped).await?;
+        }
+        Ok(())
+    }
+    /// Returns the reassembled message, carrying the delivery tags of the
+    /// other chunks, after rejecting the dropped chunks.
+    async fn reassemble(&mut self, msg: crate::Message) -> crate::Result<Option<crate::Message>> {
+        let reassembler = match &mut self.reassembler {
+            Some(reassembler) => reassembler,
+            None => return Ok(Some(msg)),
+        };
+        let msg = reassembler.push(msg).map(|(mut msg, tags)| {
+            msg.set_chunks(tags);
+            msg
+        });
+        let dropped = reassembler.take_dropped();
+        self.responder.reject_tags(dropped).await?;
+        Ok(msg)
+    }
+}
+
+#[async_trait]
+impl Admission for Consumer {
+    type Process = BoxFuture<'static, crate::Result<()>>;
+    async fn recv(&mut self) -> Option<Option<<lapin::Consumer as Stream>::Item>> {
+        Consumer::recv(self).await
+    }
+    async fn expire(&mut self) -> crate::Result<()> {
+        Consumer::expire(self).await
+    }
+    async fn admit(&mut self, req: crate::Message) -> crate::Result<Option<Self::Process>> {
+        let unsettled = self.responder.unsettled.as_ref().map(Unsettled::new);
+        let handlers = self.handle.handlers();
+        let req = match Consumer::admit(self, &handlers.peeker, req).await? {
+            Some(req) => req,
+            None => return Ok(None),
+        };
+        let processor = handlers.processor;
+        let responder = self.responder.clone();
+        Ok(Some(Box::pin(async move {
+            let _unsettled = unsettled;
+            responder.process(processor, req).await
+        })))
+    }
+}
+
+/// A delivery of the [Consumer::run] loop counted as unsettled until
+/// it's dropped.
+///
+/// [Consumer::run]: struct.Consumer.html#method.run
+struct Unsettled(Arc<AtomicUsize>);
+
+impl Unsettled {
+    fn new(count: &Arc<AtomicUsize>) -> Self {
+        count.fetch_add(1, Ordering::SeqCst);
+        Self(count.clone())
+    }
+}
+
+impl Drop for Unsettled {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Returns true in case of the `multiple` nack is requested and the
+/// message is the only unsettled one.  The `unsettled` count is `None`
+/// once the messages are reassembled or handed out with the [Acker], as
+/// those are settled out of the count.
+///
+/// [Acker]: ../ack/struct.Acker.html
+fn nack_multiple(multiple: bool, unsettled: Option<&AtomicUsize>) -> bool {
+    match unsettled {
+        Some(count) => multiple && count.load(Ordering::SeqCst) == 1,
+        None => false,
+    }
+}
+
+/// The deliveries admitted to the [Consumer::run] loop, split from the
+/// [Consumer] to drive the loop with the stub deliveries.
+///
+/// [Consumer]: struct.Consumer.html
+/// [Consumer::run]: struct.Consumer.html#method.run
+#[async_trait]
+trait Admission {
+    type Process: Future<Output = crate::Result<()>>;
+    /// Returns the next delivery, `Some(None)` once the consumer is
+    /// cancelled, or `None` once the oldest incomplete transfer expires.
+    ///
+    /// It's dropped when an in-flight message completes first, so that
+    /// it should not take the delivery out before it's ready.
+    async fn recv(&mut self) -> Option<Option<<lapin::Consumer as Stream>::Item>>;
+    /// Settles the expired transfers.
+    async fn expire(&mut self) -> crate::Result<()>;
+    /// Returns the processing of the message, or `None` in case the
+    /// message is settled on the peek failure or it waits for the other
+    /// chunks.
+    async fn admit(&mut self, req: crate::Message) -> crate::Result<Option<Self::Process>>;
+}
+
+/// The in-flight messages of the [Consumer], up to the concurrency.
+///
+/// [Consumer]: struct.Consumer.html
+struct Inflight<F> {
+    futures: FuturesUnordered<F>,
+    concurrency: usize,
+}
+
+impl<F: Future<Output = crate::Result<()>>> Inflight<F> {
+    fn new(concurrency: usize) -> Self {
+        Self {
+            futures: FuturesUnordered::new(),
+            concurrency,
+        }
+    }
+    fn is_full(&self) -> bool {
+        self.futures.len() >= self.concurrency
+    }
+    fn is_empty(&self) -> bool {
+        self.futures.is_empty()
+    }
+    fn push(&mut self, future: F) {
+        self.futures.push(future);
+    }
+    /// Returns the result of the message completed first.
+    fn next(&mut self) -> futures::stream::Next<'_, FuturesUnordered<F>> {
+        self.futures.next()
+    }
+    /// Admits the deliveries while there is room for those, and returns
+    /// once the consumer is cancelled and the in-flight messages are
+    /// settled, or on the first error.
+    async fn run<A: Admission<Process = F>>(&mut self, source: &mut A) -> crate::Result<()> {
+        loop {
+            if self.is_full() {
+                if let Some(ret) = self.next().await {
+                    ret?;
+                }
+                continue;
+            }
+            let msg = if self.is_empty() {
+                source.recv().await
+            } else {
+                let recv = source.recv();
+                futures::pin_mut!(recv);
+                match future::select(recv, self.next()).await {
+                    Either::Left((msg, _)) => msg,
+                    Either::Right((ret, _)) => {
+                        if let Some(ret) = ret {
+                            ret?;
+                        }
+                        continue;
                     }
                 }
-                Err(err) => return Err(crate::Error::from(err)),
+            };
+            let req = match msg {
+                Some(Some(Ok(msg))) => crate::Message::new(msg),
+                Some(Some(Err(err))) => return Err(crate::Error::from(err)),
+                Some(None) => break,
+                None => {
+                    source.expire().await?;
+                    continue;
+                }
+            };
+            if let Some(process) = source.admit(req).await? {
+                self.push(process);
             }
         }
+        while let Some(ret) = self.next().await {
+            ret?;
+        }
         Ok(())
     }
-    pub async fn response(&mut self, req: &crate::Message, resp: &[u8]) -> crate::Result<()> {
-        if let Some(reply_to) = req.reply_to() {
-            self.send(reply_to, resp).await?;
+}
+
+/// A [Consumer] handle to replace the [MessagePeek] and the
+/// [MessageProcess] trait objects of the running consumer, e.g. for the
+/// feature-flagged handler rollouts.
+///
+/// The replacement is atomic and used from the next delivery, while the
+/// in-flight messages complete with the previous trait objects.  Each
+/// delivery is peeked and processed by the pair of the trait objects
+/// taken at once, so that the [swap] method replaces both without mixing
+/// the old and the new ones.  The [MessageProcess] is wrapped with the
+/// builder's [Middleware] layers, as well.
+///
+/// [Consumer]: struct.Consumer.html
+/// [MessagePeek]: ../message/trait.MessagePeek.html
+/// [MessageProcess]: ../message/trait.MessageProcess.html
+/// [Middleware]: ../middleware/trait.Middleware.html
+/// [swap]: #method.swap
+#[derive(Clone)]
+pub struct ConsumerHandle {
+    handlers: Arc<RwLock<Handlers>>,
+    layers: Vec<Arc<dyn crate::middleware::Middleware + Send + Sync>>,
+}
+
+/// The peeker and the processor pair, replaced together.
+#[derive(Clone)]
+struct Handlers {
+    peeker: Arc<dyn crate::MessagePeek + Send + Sync>,
+    processor: Arc<dyn crate::MessageProcess + Send + Sync>,
+}
+
+impl ConsumerHandle {
+    fn new(
+        peeker: Arc<dyn crate::MessagePeek + Send + Sync>,
+        processor: Arc<dyn crate::MessageProcess + Send + Sync>,
+        layers: Vec<Arc<dyn crate::middleware::Middleware + Send + Sync>>,
+    ) -> Self {
+        let processor = crate::middleware::processor(processor, &layers);
+        Self {
+            handlers: Arc::new(RwLock::new(Handlers { peeker, processor })),
+            layers,
         }
-        self.ch
-            .basic_ack(req.delivery_tag(), self.ack_opts.clone())
-            .await
-            .map_err(crate::Error::from)?;
+    }
+    /// Replace both the [MessagePeek] and the [MessageProcess] trait
+    /// objects at once.
+    ///
+    /// [MessagePeek]: ../message/trait.MessagePeek.html
+    /// [MessageProcess]: ../message/trait.MessageProcess.html
+    pub fn swap(
+        &self,
+        peeker: Arc<dyn crate::MessagePeek + Send + Sync>,
+        processor: Arc<dyn crate::MessageProcess + Send + Sync>,
+    ) {
+        let processor = crate::middleware::processor(processor, &self.layers);
+        *self.write() = Handlers { peeker, processor };
+    }
+    /// Replace the [MessagePeek] trait object.
+    ///
+    /// [MessagePeek]: ../message/trait.MessagePeek.html
+    pub fn swap_peeker(&self, peeker: Arc<dyn crate::MessagePeek + Send + Sync>) {
+        self.write().peeker = peeker;
+    }
+    /// Replace the [MessageProcess] trait object.
+    ///
+    /// [MessageProcess]: ../message/trait.MessageProcess.html
+    pub fn swap_processor(&self, processor: Arc<dyn crate::MessageProcess + Send + Sync>) {
+        let processor = crate::middleware::processor(processor, &self.layers);
+        self.write().processor = processor;
+    }
+    /// Returns the current peeker and processor pair.
+    fn handlers(&self) -> Handlers {
+        self.handlers
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, Handlers> {
+        self.handlers
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A [Consumer] handle to bind and unbind the routing keys of the
+/// consumer's queue at runtime.
+///
+/// It tracks the current routing keys of the exchange and queue pair,
+/// shared with the [ConsumerBuilder], which binds those again when the
+/// consumer of the same pair is rebuilt, or with the one given the
+/// handle through the [with_bindings] method.  The queue name binding
+/// made by the builder isn't tracked.
+///
+/// [Consumer]: struct.Consumer.html
+/// [ConsumerBuilder]: struct.ConsumerBuilder.html
+/// [with_bindings]: struct.ConsumerBuilder.html#method.with_bindings
+#[derive(Clone)]
+pub struct Bindings {
+    ch: lapin::Channel,
+    ex: String,
+    /// The declared queue name, e.g. assigned by the broker.
+    queue: String,
+    /// The queue name given to the builder, to track the routing keys.
+    key: String,
+    bind_opts: lapin::options::QueueBindOptions,
+    bind_field: lapin::types::FieldTable,
+    routing_keys: RoutingKeys,
+}
+
+impl Bindings {
+    /// Bind the queue to the consumer's exchange with the `routing_key`.
+    pub async fn bind(&self, routing_key: &str) -> crate::Result<()> {
+        self.queue_bind(routing_key).await?;
+        self.routing_keys.insert(&self.ex, &self.key, routing_key);
         Ok(())
     }
-    pub async fn reject(&mut self, req: &crate::Message) -> crate::Result<()> {
+    /// Unbind the `routing_key` from the queue.
+    pub async fn unbind(&self, routing_key: &str) -> crate::Result<()> {
         self.ch
-            .basic_reject(req.delivery_tag(), self.rej_opts.clone())
+            .queue_unbind(
+                &self.queue,
+                &self.ex,
+                routing_key,
+                lapin::options::QueueUnbindOptions::default(),
+                self.bind_field.clone(),
+            )
             .await
             .map_err(crate::Error::from)?;
+        self.routing_keys.remove(&self.ex, &self.key, routing_key);
+        Ok(())
+    }
+    /// Returns the current routing keys.
+    pub fn routing_keys(&self) -> Vec<String> {
+        self.routing_keys.get(&self.ex, &self.key)
+    }
+    /// Binds all the current routing keys, e.g. after the reconnect, or
+    /// the `initial` ones for the first time.
+    async fn restore(&self, initial: &BTreeSet<String>) -> crate::Result<()> {
+        for routing_key in self.routing_keys.init(&self.ex, &self.key, initial) {
+            self.queue_bind(&routing_key).await?;
+        }
         Ok(())
     }
-    async fn send(&mut self, routing_key: &str, msg: &[u8]) -> crate::Result<()> {
+    async fn queue_bind(&self, routing_key: &str) -> crate::Result<()> {
         self.ch
-            .basic_publish(
+            .queue_bind(
+                &self.queue,
                 &self.ex,
-                &routing_key,
-                self.tx_opts.clone(),
-                msg.to_vec(),
-                self.tx_props.clone(),
+                routing_key,
+                self.bind_opts.clone(),
+                self.bind_field.clone(),
             )
             .await
+            .map_err(crate::Error::from)
+    }
+}
+
+/// The routing keys of each exchange and queue pair, shared by the
+/// [ConsumerBuilder] and the [Bindings] handles.
+///
+/// [ConsumerBuilder]: struct.ConsumerBuilder.html
+/// [Bindings]: struct.Bindings.html
+#[derive(Clone, Default)]
+struct RoutingKeys(Arc<Mutex<RoutingKeyMap>>);
+
+type RoutingKeyMap = BTreeMap<(String, String), BTreeSet<String>>;
+
+impl RoutingKeys {
+    /// Returns the routing keys of the pair, starting with the `initial`
+    /// ones in case the pair isn't tracked yet.
+    fn init(&self, ex: &str, queue: &str, initial: &BTreeSet<String>) -> Vec<String> {
+        self.lock()
+            .entry((ex.to_string(), queue.to_string()))
+            .or_insert_with(|| initial.clone())
+            .iter()
+            .cloned()
+            .collect()
+    }
+    fn insert(&self, ex: &str, queue: &str, routing_key: &str) {
+        self.lock()
+            .entry((ex.to_string(), queue.to_string()))
+            .or_default()
+            .insert(routing_key.to_string());
+    }
+    fn remove(&self, ex: &str, queue: &str, routing_key: &str) {
+        if let Some(keys) = self.lock().get_mut(&(ex.to_string(), queue.to_string())) {
+            keys.remove(routing_key);
+        }
+    }
+    fn get(&self, ex: &str, queue: &str) -> Vec<String> {
+        self.lock()
+            .get(&(ex.to_string(), queue.to_string()))
+            .map(|keys| keys.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+    fn lock(&self) -> std::sync::MutexGuard<'_, RoutingKeyMap> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A [Consumer] reply and acknowledgement sender, shared by the messages
+/// processed concurrently.
+///
+/// [Consumer]: struct.Consumer.html
+#[derive(Clone)]
+struct Responder {
+    ch: lapin::Channel,
+    ex: String,
+    tx_props: lapin::BasicProperties,
+    tx_opts: lapin::options::BasicPublishOptions,
+    unsettled: Option<Arc<AtomicUsize>>,
+    copy_content_type: bool,
+    upcasters: Option<crate::schema::Upcasters>,
+    #[cfg(feature = "json-schema")]
+    validator: Option<crate::validate::Validator>,
+    dead_letter: Option<(String, String)>,
+    ack_opts: lapin::options::BasicAckOptions,
+    rej_opts: lapin::options::BasicRejectOptions,
+    #[cfg(feature = "encryption")]
+    encryption: Option<crate::crypto::Encryption>,
+    retry: Option<(crate::retry::RetryPolicy, String)>,
+}
+
+impl Responder {
+    async fn process(
+        self,
+        processor: Arc<dyn crate::MessageProcess + Send + Sync>,
+        mut req: crate::Message,
+    ) -> crate::Result<()> {
+        let raw = self.raw(&req);
+        if !self.prepare(&mut req, raw.clone()).await? {
+            return Ok(());
+        }
+        match processor.process_reply(&req).await {
+            Ok(reply) => self.response(&req, reply).await,
+            Err(crate::MessageError::Drop) => self.ack(&req).await,
+            Err(err) => match (&self.retry, raw) {
+                (Some(_), Some((_, raw))) if err.requeue() => {
+                    self.error_reply(&req, &err).await?;
+                    self.retry(&req, raw).await
+                }
+                (_, raw) => self.fail(&req, err, raw).await,
+            },
+        }
+    }
+    /// Returns the original message to retry, or to dead-letter, as it's
+    /// decrypted and upcasted in place.  The reassembled messages are
+    /// neither retried nor dead-lettered.
+    fn raw(&self, req: &crate::Message) -> Option<(lapin::BasicProperties, Vec<u8>)> {
+        if (self.retry.is_some() || self.dead_letter.is_some()) && req.chunks().is_empty() {
+            Some((req.properties().clone(), req.data().to_vec()))
+        } else {
+            None
+        }
+    }
+    /// Decrypts, upcasts and validates the message in place, and returns
+    /// false once the message is settled on failure.
+    async fn prepare(
+        &self,
+        req: &mut crate::Message,
+        raw: Option<(lapin::BasicProperties, Vec<u8>)>,
+    ) -> crate::Result<bool> {
+        if !self.decrypt(req) {
+            self.reject(req).await?;
+            return Ok(false);
+        }
+        if let Err(err) = self.validate(req) {
+            self.fail(req, err, raw).await?;
+            return Ok(false);
+        }
+        Ok(true)
+    }
+    /// Upcasts and validates the decrypted message.
+    fn validate(&self, req: &mut crate::Message) -> Result<(), crate::MessageError> {
+        if let Some(upcasters) = &self.upcasters {
+            upcasters.upcast(req)?;
+        }
+        #[cfg(feature = "json-schema")]
+        {
+            if let Some(validator) = &self.validator {
+                validator.validate(req)?;
+            }
+        }
+        Ok(())
+    }
+    /// Sends the error reply, if any, and settles the message, as well as
+    /// the other chunks, with the error action.
+    async fn fail(
+        &self,
+        req: &crate::Message,
+        err: crate::MessageError,
+        raw: Option<(lapin::BasicProperties, Vec<u8>)>,
+    ) -> crate::Result<()> {
+        self.error_reply(req, &err).await?;
+        self.discard(req, err, raw).await
+    }
+    /// Settles the message, as well as the other chunks, with the error
+    /// action without the error reply.
+    ///
+    /// The message rejected without requeue is dead-lettered with the error
+    /// reason, in case the `raw` original message is provided.  The other
+    /// failures are left to the broker's dead-lettering.
+    async fn discard(
+        &self,
+        req: &crate::Message,
+        err: crate::MessageError,
+        raw: Option<(lapin::BasicProperties, Vec<u8>)>,
+    ) -> crate::Result<()> {
+        if let crate::MessageError::Reject {
+            requeue: false,
+            reason: Some(reason),
+            ..
+        } = &err
+        {
+            if let (Some(raw), true) = (raw, self.dead_letter.is_some()) {
+                return self.dead_letter(req, raw, reason).await;
+            }
+        }
+        match err {
+            crate::MessageError::Drop => self.ack(req).await,
+            crate::MessageError::Reject { requeue, .. } => {
+                let opts = lapin::options::BasicRejectOptions { requeue };
+                if req.settle() {
+                    for tag in
+                        std::iter::once(req.delivery_tag()).chain(req.chunks().iter().copied())
+                    {
+                        self.ch
+                            .basic_reject(tag, opts.clone())
+                            .await
+                            .map_err(crate::Error::from)?;
+                    }
+                }
+                Ok(())
+            }
+            crate::MessageError::Nack {
+                requeue, multiple, ..
+            } => {
+                if !req.settle() {
+                    return Ok(());
+                }
+                // Nack the other chunks first not to nack those twice
+                // with the multiple flag.
+                let opts = lapin::options::BasicNackOptions {
+                    multiple: false,
+                    requeue,
+                };
+                for tag in req.chunks() {
+                    self.ch
+                        .basic_nack(*tag, opts.clone())
+                        .await
+                        .map_err(crate::Error::from)?;
+                }
+                let opts = lapin::options::BasicNackOptions {
+                    multiple: nack_multiple(multiple, self.unsettled.as_deref()),
+                    requeue,
+                };
+                self.ch
+                    .basic_nack(req.delivery_tag(), opts)
+                    .await
+                    .map_err(crate::Error::from)
+            }
+        }
+    }
+    /// Republishes the original message to the dead-letter exchange with
+    /// the error reason, and acknowledges the message as well as the other
+    /// chunks.
+    async fn dead_letter(
+        &self,
+        req: &crate::Message,
+        raw: (lapin::BasicProperties, Vec<u8>),
+        reason: &str,
+    ) -> crate::Result<()> {
+        if let Some((ex, queue)) = &self.dead_letter {
+            let (props, data) = raw;
+            let props = crate::dlq::properties(req, props, reason);
+            self.ch
+                .basic_publish(ex, queue, self.tx_opts.clone(), data, props)
+                .await
+                .map_err(crate::Error::from)?;
+        }
+        self.ack(req).await
+    }
+    /// Sends the error reply, if any.
+    async fn error_reply(
+        &self,
+        req: &crate::Message,
+        err: &crate::MessageError,
+    ) -> crate::Result<()> {
+        if let (Some(reply_to), Some(reply)) = (req.reply_to(), err.reply()) {
+            let props = err.properties(self.reply_properties(req));
+            self.send(reply_to, props, &reply).await?;
+        }
+        Ok(())
+    }
+    /// Republishes the original payload to the retry queue, or to the
+    /// parking queue, and acknowledges the message.
+    async fn retry(&self, req: &crate::Message, raw: Vec<u8>) -> crate::Result<()> {
+        if let Some((policy, queue)) = &self.retry {
+            let (queue, props) = policy.next(queue, req);
+            self.ch
+                .basic_publish(
+                    crate::DEFAULT_EXCHANGE,
+                    &queue,
+                    self.tx_opts.clone(),
+                    raw,
+                    props,
+                )
+                .await
+                .map_err(crate::Error::from)?;
+        }
+        self.ack(req).await
+    }
+    async fn response(&self, req: &crate::Message, reply: crate::Reply) -> crate::Result<()> {
+        if let Some(reply_to) = req.reply_to() {
+            let props = reply.properties(self.reply_properties(req));
+            self.send(reply_to, props, reply.data()).await?;
+        }
+        self.ack(req).await
+    }
+    /// Returns the reply properties with the request's correlation ID,
+    /// as well as the content type and encoding if those are copied.
+    fn reply_properties(&self, req: &crate::Message) -> lapin::BasicProperties {
+        let mut props = self.tx_props.clone();
+        if let Some(id) = req.correlation_id() {
+            props = props.with_correlation_id(id.into());
+        }
+        if self.copy_content_type {
+            if let Some(content_type) = req.content_type() {
+                props = props.with_content_type(content_type.into());
+            }
+            if let Some(content_encoding) = req.content_encoding() {
+                props = props.with_content_encoding(content_encoding.into());
+            }
+        }
+        props
+    }
+    /// Acknowledges the message, as well as the other chunks.
+    async fn ack(&self, req: &crate::Message) -> crate::Result<()> {
+        if req.settle() {
+            self.ch
+                .basic_ack(req.delivery_tag(), self.ack_opts.clone())
+                .await
+                .map_err(crate::Error::from)?;
+            for tag in req.chunks() {
+                self.ch
+                    .basic_ack(*tag, self.ack_opts.clone())
+                    .await
+                    .map_err(crate::Error::from)?;
+            }
+        }
+        Ok(())
+    }
+    /// Rejects the message, as well as the other chunks.
+    async fn reject(&self, req: &crate::Message) -> crate::Result<()> {
+        if req.settle() {
+            self.ch
+                .basic_reject(req.delivery_tag(), self.rej_opts.clone())
+                .await
+                .map_err(crate::Error::from)?;
+            self.reject_tags(req.chunks().to_vec()).await?;
+        }
+        Ok(())
+    }
+    async fn reject_tags(&self, tags: Vec<u64>) -> crate::Result<()> {
+        for tag in tags {
+            self.ch
+                .basic_reject(tag, self.rej_opts.clone())
+                .await
+                .map_err(crate::Error::from)?;
+        }
+        Ok(())
+    }
+    async fn send(
+        &self,
+        routing_key: &str,
+        props: lapin::BasicProperties,
+        msg: &[u8],
+    ) -> crate::Result<()> {
+        let (props, msg) = self.encrypt(props, msg)?;
+        self.ch
+            .basic_publish(&self.ex, routing_key, self.tx_opts.clone(), msg, props)
+            .await
             .map_err(crate::Error::from)?;
         Ok(())
     }
+    #[cfg(feature = "encryption")]
+    fn encrypt(
+        &self,
+        props: lapin::BasicProperties,
+        msg: &[u8],
+    ) -> crate::Result<(lapin::BasicProperties, Vec<u8>)> {
+        match &self.encryption {
+            Some(encryption) => encryption.encrypt(props, msg),
+            None => Ok((props, msg.to_vec())),
+        }
+    }
+    #[cfg(not(feature = "encryption"))]
+    fn encrypt(
+        &self,
+        props: lapin::BasicProperties,
+        msg: &[u8],
+    ) -> crate::Result<(lapin::BasicProperties, Vec<u8>)> {
+        Ok((props, msg.to_vec()))
+    }
+    /// Decrypts the message in place and returns false on failure.
+    #[cfg(feature = "encryption")]
+    fn decrypt(&self, msg: &mut crate::Message) -> bool {
+        match &self.encryption {
+            Some(encryption) => match encryption.decrypt(msg) {
+                Some(data) => {
+                    msg.set_data(data);
+                    true
+                }
+                None => false,
+            },
+            None => true,
+        }
+    }
+    #[cfg(not(feature = "encryption"))]
+    fn decrypt(&self, _msg: &mut crate::Message) -> bool {
+        true
+    }
 }
 
+/// The stream of the messages, each carrying the [Acker] handle unless
+/// those are consumed with the `no_ack` option.
+///
+/// The messages are streamed as delivered, without being peeked,
+/// reassembled, decrypted, upcasted nor validated as the [run] method
+/// does.
+///
+/// [run]: struct.Consumer.html#method.run
+/// [Acker]: ../ack/struct.Acker.html
 impl Stream for Consumer {
     type Item = Result<crate::Message, crate::Error>;
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.responder.unsettled = None;
         let c = &mut self.consume;
         let c = Pin::new(c);
         match c.poll_next(cx) {
+            Poll::Ready(Some(Ok(msg))) if self.ack => {
+                let ch = self.responder.ch.clone();
+                Poll::Ready(Some(Ok(crate::Message::with_acker(msg, ch))))
+            }
             Poll::Ready(Some(Ok(msg))) => Poll::Ready(Some(Ok(crate::Message::new(msg)))),
             Poll::Ready(Some(Err(err))) => Poll::Ready(Some(Err(err.into()))),
             Poll::Ready(None) => Poll::Ready(None),
@@ -184,3 +1343,265 @@ impl Stream for Consumer {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::message::{EchoProcessor, NoopPeeker};
+    use crate::MessageError;
+    use async_trait::async_trait;
+    use futures::channel::oneshot;
+    use std::collections::BTreeSet;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    struct Fixed(&'static [u8]);
+    #[async_trait]
+    impl crate::MessageProcess for Fixed {
+        async fn process(&self, _: &crate::Message) -> Result<Vec<u8>, MessageError> {
+            Ok(self.0.to_vec())
+        }
+    }
+    struct Reject;
+    #[async_trait]
+    impl crate::MessagePeek for Reject {
+        async fn peek(&self, _: &crate::Message) -> Result<(), MessageError> {
+            Err(MessageError::reject())
+        }
+    }
+    struct Counter(AtomicUsize);
+    #[async_trait]
+    impl crate::MessageProcess for Counter {
+        async fn process(&self, msg: &crate::Message) -> Result<Vec<u8>, MessageError> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(msg.data().to_vec())
+        }
+    }
+    #[test]
+    fn inflight() {
+        let processor = Arc::new(Counter(AtomicUsize::new(0)));
+        let done = Arc::new(Mutex::new(Vec::new()));
+        let mut inflight = super::Inflight::new(2);
+        let mut senders = Vec::new();
+        for tag in 1..=4 {
+            if inflight.is_full() {
+                break;
+            }
+            let (tx, rx) = oneshot::channel::<()>();
+            senders.push(tx);
+            let processor = processor.clone();
+            let done = done.clone();
+            inflight.push(async move {
+                let msg = crate::message::test_message(
+                    tag,
+                    "",
+                    "",
+                    lapin::BasicProperties::default(),
+                    b"data",
+                );
+                let _ = rx.await;
+                crate::MessageProcess::process(processor.as_ref(), &msg)
+                    .await
+                    .unwrap();
+                done.lock().unwrap().push(msg.delivery_tag());
+                Ok(())
+            });
+        }
+        assert_eq!(2, senders.len());
+        assert!(inflight.is_full());
+        futures::executor::block_on(async {
+            senders.pop().unwrap().send(()).unwrap();
+            assert_eq!(Some(Ok(())), inflight.next().await);
+            assert!(!inflight.is_full());
+            senders.pop().unwrap().send(()).unwrap();
+            assert_eq!(Some(Ok(())), inflight.next().await);
+            assert_eq!(None, inflight.next().await);
+        });
+        assert!(inflight.is_empty());
+        assert_eq!(vec![2, 1], *done.lock().unwrap());
+        assert_eq!(2, processor.0.load(Ordering::SeqCst));
+    }
+    /// The stub deliveries, of which the first one is processed only once
+    /// the third one is settled.
+    struct Stub {
+        tags: std::collections::VecDeque<u64>,
+        gate: Option<oneshot::Receiver<()>>,
+        release: Arc<Mutex<Option<oneshot::Sender<()>>>>,
+        inflight: Arc<AtomicUsize>,
+        max: usize,
+        settled: Arc<Mutex<Vec<u64>>>,
+    }
+    #[async_trait]
+    impl super::Admission for Stub {
+        type Process = futures::future::BoxFuture<'static, crate::Result<()>>;
+        async fn recv(&mut self) -> Option<Option<lapin::Result<lapin::message::Delivery>>> {
+            assert!(self.inflight.load(Ordering::SeqCst) < 2);
+            let tag = self.tags.pop_front();
+            Some(tag.map(|tag| {
+                Ok(lapin::message::Delivery::new(
+                    tag,
+                    "".into(),
+                    "".into(),
+                    false,
+                ))
+            }))
+        }
+        async fn expire(&mut self) -> crate::Result<()> {
+            Ok(())
+        }
+        async fn admit(&mut self, req: crate::Message) -> crate::Result<Option<Self::Process>> {
+            let tag = req.delivery_tag();
+            let inflight = self.inflight.clone();
+            self.max = self.max.max(inflight.fetch_add(1, Ordering::SeqCst) + 1);
+            let gate = if tag == 1 { self.gate.take() } else { None };
+            let release = if tag == 3 {
+                self.release.lock().unwrap().take()
+            } else {
+                None
+            };
+            let settled = self.settled.clone();
+            Ok(Some(Box::pin(async move {
+                if let Some(gate) = gate {
+                    gate.await.unwrap();
+                }
+                if let Some(release) = release {
+                    release.send(()).unwrap();
+                }
+                inflight.fetch_sub(1, Ordering::SeqCst);
+                settled.lock().unwrap().push(tag);
+                Ok(())
+            })))
+        }
+    }
+    #[test]
+    fn run_admission() {
+        let (tx, rx) = oneshot::channel();
+        let mut stub = Stub {
+            tags: (1..=4).collect(),
+            gate: Some(rx),
+            release: Arc::new(Mutex::new(Some(tx))),
+            inflight: Arc::new(AtomicUsize::new(0)),
+            max: 0,
+            settled: Arc::new(Mutex::new(Vec::new())),
+        };
+        let mut inflight = super::Inflight::new(2);
+        futures::executor::block_on(inflight.run(&mut stub)).unwrap();
+        assert_eq!(2, stub.max);
+        assert_eq!(0, stub.inflight.load(Ordering::SeqCst));
+        assert_eq!(vec![2, 3, 1, 4], *stub.settled.lock().unwrap());
+    }
+    #[test]
+    fn nack_multiple() {
+        struct Test {
+            name: &'static str,
+            multiple: bool,
+            unsettled: Option<usize>,
+            want: bool,
+        }
+        let tests = [
+            Test {
+                name: "only unsettled message",
+                multiple: true,
+                unsettled: Some(1),
+                want: true,
+            },
+            Test {
+                name: "other message in-flight",
+                multiple: true,
+                unsettled: Some(2),
+                want: false,
+            },
+            Test {
+                name: "reassembled or handed out messages",
+                multiple: true,
+                unsettled: None,
+                want: false,
+            },
+            Test {
+                name: "single nack",
+                multiple: false,
+                unsettled: Some(1),
+                want: false,
+            },
+        ];
+        for t in &tests {
+            let count = Arc::new(AtomicUsize::new(0));
+            let guards: Vec<_> = (0..t.unsettled.unwrap_or(0))
+                .map(|_| super::Unsettled::new(&count))
+                .collect();
+            let unsettled = t.unsettled.map(|_| count.as_ref());
+            let got = super::nack_multiple(t.multiple, unsettled);
+            assert_eq!(t.want, got, "{}", t.name);
+            drop(guards);
+            assert_eq!(0, count.load(Ordering::SeqCst), "{}", t.name);
+        }
+    }
+    #[test]
+    fn routing_keys() {
+        let keys = super::RoutingKeys::default();
+        let initial: BTreeSet<String> = vec![String::from("a.*")].into_iter().collect();
+        assert_eq!(vec!["a.*"], keys.init("ex", "a", &initial));
+        keys.insert("ex", "a", "a.#");
+        keys.remove("ex", "a", "a.*");
+        assert_eq!(vec!["a.#"], keys.get("ex", "a"));
+        // Restores the runtime bindings of the same pair only.
+        assert_eq!(vec!["a.#"], keys.init("ex", "a", &initial));
+        assert_eq!(vec!["a.*"], keys.init("ex", "b", &initial));
+        assert_eq!(vec!["a.*"], keys.init("other", "a", &initial));
+        keys.remove("ex", "b", "a.*");
+        assert!(keys.init("ex", "b", &initial).is_empty());
+        assert!(keys.get("ex", "c").is_empty());
+    }
+    #[test]
+    fn routing_keys_rebuild() {
+        // The server-named queue is tracked by the empty queue name given
+        // to the builder, and restored by the second builder given the
+        // first consumer's bindings, e.g. over the new connection.
+        let first = super::RoutingKeys::default();
+        let initial: BTreeSet<String> = vec![String::from("a.*")].into_iter().collect();
+        assert_eq!(vec!["a.*"], first.init("ex", "", &initial));
+        first.insert("ex", "", "a.#");
+        first.remove("ex", "", "a.*");
+        let mut second = super::RoutingKeys::default();
+        assert_eq!(vec!["a.*"], second.init("ex", "", &initial));
+        second = first.clone();
+        assert_eq!(vec!["a.#"], second.init("ex", "", &initial));
+        second.insert("ex", "", "b.#");
+        assert_eq!(vec!["a.#", "b.#"], first.get("ex", ""));
+    }
+    #[test]
+    fn handle_swap() {
+        let handle =
+            super::ConsumerHandle::new(Arc::new(NoopPeeker {}), Arc::new(EchoProcessor {}), vec![]);
+        let msg =
+            crate::message::test_message(1, "", "", lapin::BasicProperties::default(), b"data");
+        let before = handle.handlers();
+        let other = handle.clone();
+        other.swap(Arc::new(Reject), Arc::new(Fixed(b"swapped")));
+        let swapped = handle.handlers();
+        other.swap_processor(Arc::new(Fixed(b"processor")));
+        let processor = handle.handlers();
+        other.swap_peeker(Arc::new(NoopPeeker {}));
+        let peeker = handle.handlers();
+        futures::executor::block_on(async {
+            assert_eq!(Ok(()), before.peeker.peek(&msg).await);
+            assert_eq!(Ok(b"data".to_vec()), before.processor.process(&msg).await);
+            assert_eq!(Err(MessageError::reject()), swapped.peeker.peek(&msg).await);
+            assert_eq!(
+                Ok(b"swapped".to_vec()),
+                swapped.processor.process(&msg).await
+            );
+            assert_eq!(
+                Err(MessageError::reject()),
+                processor.peeker.peek(&msg).await
+            );
+            assert_eq!(
+                Ok(b"processor".to_vec()),
+                processor.processor.process(&msg).await
+            );
+            assert_eq!(Ok(()), peeker.peeker.peek(&msg).await);
+            assert_eq!(
+                Ok(b"processor".to_vec()),
+                peeker.processor.process(&msg).await
+            );
+        });
+    }
+}
@@ -0,0 +1,427 @@
+// SPDX-License-Identifier: Apache-2.0 AND MIT
+//! `Encryption` struct, `Algorithm` enum and `KeyProvider` trait
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// The header name carrying the key ID used to encrypt the message.
+pub const KEY_ID_HEADER: &str = "x-encryption-key-id";
+/// The header name carrying the encryption algorithm name.
+pub const ALGORITHM_HEADER: &str = "x-encryption-algorithm";
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+
+/// An AEAD algorithm enum.
+///
+/// [Aes256Gcm] is always available through the `encryption` feature,
+/// which the `aes` feature is an alias of, and [ChaCha20Poly1305] is
+/// available through the `chacha` feature.
+///
+/// [Aes256Gcm]: #variant.Aes256Gcm
+/// [ChaCha20Poly1305]: #variant.ChaCha20Poly1305
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Algorithm {
+    /// AES-256-GCM.
+    #[cfg(feature = "aes-gcm")]
+    Aes256Gcm,
+    /// ChaCha20-Poly1305.
+    #[cfg(feature = "chacha")]
+    ChaCha20Poly1305,
+}
+
+impl Algorithm {
+    /// Returns the algorithm name carried by the message header.
+    pub fn name(self) -> &'static str {
+        match self {
+            #[cfg(feature = "aes-gcm")]
+            Self::Aes256Gcm => "AES-256-GCM",
+            #[cfg(feature = "chacha")]
+            Self::ChaCha20Poly1305 => "ChaCha20-Poly1305",
+        }
+    }
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            #[cfg(feature = "aes-gcm")]
+            "AES-256-GCM" => Some(Self::Aes256Gcm),
+            #[cfg(feature = "chacha")]
+            "ChaCha20-Poly1305" => Some(Self::ChaCha20Poly1305),
+            _ => None,
+        }
+    }
+    fn seal(self, key: &[u8], nonce: &[u8], data: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            #[cfg(feature = "aes-gcm")]
+            Self::Aes256Gcm => {
+                use aes_gcm::aead::{generic_array::GenericArray, Aead, NewAead, Payload};
+                aes_gcm::Aes256Gcm::new(GenericArray::from_slice(key))
+                    .encrypt(GenericArray::from_slice(nonce), Payload { msg: data, aad })
+                    .ok()
+            }
+            #[cfg(feature = "chacha")]
+            Self::ChaCha20Poly1305 => {
+                use chacha20poly1305::aead::{generic_array::GenericArray, Aead, NewAead, Payload};
+                chacha20poly1305::ChaCha20Poly1305::new(GenericArray::from_slice(key))
+                    .encrypt(GenericArray::from_slice(nonce), Payload { msg: data, aad })
+                    .ok()
+            }
+        }
+    }
+    fn open(self, key: &[u8], nonce: &[u8], data: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            #[cfg(feature = "aes-gcm")]
+            Self::Aes256Gcm => {
+                use aes_gcm::aead::{generic_array::GenericArray, Aead, NewAead, Payload};
+                aes_gcm::Aes256Gcm::new(GenericArray::from_slice(key))
+                    .decrypt(GenericArray::from_slice(nonce), Payload { msg: data, aad })
+                    .ok()
+            }
+            #[cfg(feature = "chacha")]
+            Self::ChaCha20Poly1305 => {
+                use chacha20poly1305::aead::{generic_array::GenericArray, Aead, NewAead, Payload};
+                chacha20poly1305::ChaCha20Poly1305::new(GenericArray::from_slice(key))
+                    .decrypt(GenericArray::from_slice(nonce), Payload { msg: data, aad })
+                    .ok()
+            }
+        }
+    }
+}
+
+/// A trait to look up the 256 bits encryption keys by the key ID.
+pub trait KeyProvider {
+    /// Returns the key ID used to encrypt the outgoing messages.
+    fn current_key_id(&self) -> String;
+    /// Returns the key of the `key_id`.
+    fn key(&self, key_id: &str) -> Option<Vec<u8>>;
+}
+
+/// A [KeyProvider] implementation which holds the keys in memory.
+///
+/// [KeyProvider]: trait.KeyProvider.html
+#[derive(Clone, Default)]
+pub struct KeyRing {
+    current: String,
+    keys: HashMap<String, Vec<u8>>,
+}
+
+impl KeyRing {
+    pub fn new() -> Self {
+        Self {
+            ..Default::default()
+        }
+    }
+    /// Add the key and make it current for the outgoing messages.
+    pub fn current_key(&mut self, key_id: &str, key: &[u8]) -> &mut Self {
+        self.current = key_id.to_string();
+        self.key(key_id, key)
+    }
+    /// Add the key only to decrypt the incoming messages.
+    pub fn key(&mut self, key_id: &str, key: &[u8]) -> &mut Self {
+        self.keys.insert(key_id.to_string(), key.to_vec());
+        self
+    }
+}
+
+impl KeyProvider for KeyRing {
+    fn current_key_id(&self) -> String {
+        self.current.clone()
+    }
+    fn key(&self, key_id: &str) -> Option<Vec<u8>> {
+        self.keys.get(key_id).cloned()
+    }
+}
+
+/// A payload encryption used both by the [Producer] and the [Consumer].
+///
+/// The encrypted payload is the nonce followed by the ciphertext, and the
+/// key ID and the algorithm name are carried by the message headers as
+/// well as authenticated as the associated data.  The other properties
+/// and headers are not authenticated, see [Signer] for those.
+///
+/// [Producer]: ../produce/struct.Producer.html
+/// [Consumer]: ../consume/struct.Consumer.html
+/// [Signer]: ../sign/struct.Signer.html
+#[derive(Clone)]
+pub struct Encryption {
+    algorithm: Algorithm,
+    keys: Arc<dyn KeyProvider + Send + Sync>,
+}
+
+impl Encryption {
+    pub fn new(algorithm: Algorithm, keys: Arc<dyn KeyProvider + Send + Sync>) -> Self {
+        Self { algorithm, keys }
+    }
+    /// Returns the properties with the encryption headers set
+    /// and the encrypted `data`.
+    pub(crate) fn encrypt(
+        &self,
+        props: lapin::BasicProperties,
+        data: &[u8],
+    ) -> crate::Result<(lapin::BasicProperties, Vec<u8>)> {
+        let key_id = self.keys.current_key_id();
+        let key = match self.keys.key(&key_id) {
+            Some(key) if key.len() == KEY_LEN => key,
+            _ => return Err(crate::Error::Crypto(format!("invalid key: {}", key_id))),
+        };
+        let nonce: [u8; NONCE_LEN] = rand::random();
+        let mut buf = nonce.to_vec();
+        let aad = aad(&key_id, self.algorithm);
+        match self.algorithm.seal(&key, &nonce, data, &aad) {
+            Some(ciphertext) => buf.extend(ciphertext),
+            None => return Err(crate::Error::Crypto(String::from("encryption failure"))),
+        }
+        let props = crate::message::with_header(
+            props,
+            KEY_ID_HEADER,
+            lapin::types::AMQPValue::LongString(key_id.into()),
+        );
+        let props = crate::message::with_header(
+            props,
+            ALGORITHM_HEADER,
+            lapin::types::AMQPValue::LongString(self.algorithm.name().into()),
+        );
+        Ok((props, buf))
+    }
+    /// Returns the decrypted payload of the message, or `None` in case
+    /// the message is not encrypted or it fails to decrypt.
+    pub(crate) fn decrypt(&self, msg: &crate::Message) -> Option<Vec<u8>> {
+        let key_id = msg
+            .header_value(KEY_ID_HEADER)
+            .and_then(crate::message::header_str)?;
+        let algorithm = msg
+            .header_value(ALGORITHM_HEADER)
+            .and_then(crate::message::header_str)
+            .and_then(Algorithm::from_name)?;
+        let key = self.keys.key(key_id).filter(|key| key.len() == KEY_LEN)?;
+        let data = msg.data();
+        if data.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        algorithm.open(&key, nonce, ciphertext, &aad(key_id, algorithm))
+    }
+}
+
+/// Returns the associated data binding the key ID and the algorithm
+/// headers to the ciphertext.
+fn aad(key_id: &str, algorithm: Algorithm) -> Vec<u8> {
+    format!("{}\n{}", algorithm.name(), key_id).into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn seal_then_open() {
+        struct Test {
+            name: &'static str,
+            algorithm: super::Algorithm,
+            tamper: bool,
+            key_id: &'static str,
+            want: Option<&'static [u8]>,
+        }
+        let tests = [
+            #[cfg(feature = "aes-gcm")]
+            Test {
+                name: "AES-256-GCM",
+                algorithm: super::Algorithm::Aes256Gcm,
+                tamper: false,
+                key_id: "key1",
+                want: Some(b"some data"),
+            },
+            #[cfg(feature = "aes-gcm")]
+            Test {
+                name: "tampered AES-256-GCM",
+                algorithm: super::Algorithm::Aes256Gcm,
+                tamper: true,
+                key_id: "key1",
+                want: None,
+            },
+            #[cfg(feature = "aes-gcm")]
+            Test {
+                name: "swapped key ID header AES-256-GCM",
+                algorithm: super::Algorithm::Aes256Gcm,
+                tamper: false,
+                key_id: "key2",
+                want: None,
+            },
+            #[cfg(feature = "chacha")]
+            Test {
+                name: "ChaCha20-Poly1305",
+                algorithm: super::Algorithm::ChaCha20Poly1305,
+                tamper: false,
+                key_id: "key1",
+                want: Some(b"some data"),
+            },
+            #[cfg(feature = "chacha")]
+            Test {
+                name: "tampered ChaCha20-Poly1305",
+                algorithm: super::Algorithm::ChaCha20Poly1305,
+                tamper: true,
+                key_id: "key1",
+                want: None,
+            },
+            #[cfg(feature = "chacha")]
+            Test {
+                name: "swapped key ID header ChaCha20-Poly1305",
+                algorithm: super::Algorithm::ChaCha20Poly1305,
+                tamper: false,
+                key_id: "key2",
+                want: None,
+            },
+        ];
+        let key = [7u8; super::KEY_LEN];
+        let nonce = [1u8; super::NONCE_LEN];
+        for t in &tests {
+            let aad = super::aad("key1", t.algorithm);
+            let mut data = t.algorithm.seal(&key, &nonce, b"some data", &aad).unwrap();
+            if t.tamper {
+                data[0] ^= 0xff;
+            }
+            let aad = super::aad(t.key_id, t.algorithm);
+            let got = t.algorithm.open(&key, &nonce, &data, &aad);
+            assert_eq!(t.want.map(|want| want.to_vec()), got, "{}", t.name);
+            assert_eq!(
+                Some(t.algorithm),
+                super::Algorithm::from_name(t.algorithm.name()),
+                "{}",
+                t.name
+            );
+        }
+    }
+    #[test]
+    fn encrypt_then_decrypt() {
+        use std::sync::Arc;
+        struct Test {
+            name: &'static str,
+            algorithm: super::Algorithm,
+            key_id: Option<&'static str>,
+            algorithm_name: Option<&'static str>,
+            tamper: bool,
+            want: Option<&'static [u8]>,
+        }
+        let tests = [
+            #[cfg(feature = "aes-gcm")]
+            Test {
+                name: "AES-256-GCM",
+                algorithm: super::Algorithm::Aes256Gcm,
+                key_id: None,
+                algorithm_name: None,
+                tamper: false,
+                want: Some(b"some data"),
+            },
+            #[cfg(feature = "aes-gcm")]
+            Test {
+                name: "changed key ID AES-256-GCM",
+                algorithm: super::Algorithm::Aes256Gcm,
+                key_id: Some("key2"),
+                algorithm_name: None,
+                tamper: false,
+                want: None,
+            },
+            #[cfg(feature = "aes-gcm")]
+            Test {
+                name: "unknown algorithm AES-256-GCM",
+                algorithm: super::Algorithm::Aes256Gcm,
+                key_id: None,
+                algorithm_name: Some("AES-128-GCM"),
+                tamper: false,
+                want: None,
+            },
+            #[cfg(feature = "aes-gcm")]
+            Test {
+                name: "tampered ciphertext AES-256-GCM",
+                algorithm: super::Algorithm::Aes256Gcm,
+                key_id: None,
+                algorithm_name: None,
+                tamper: true,
+                want: None,
+            },
+            #[cfg(feature = "chacha")]
+            Test {
+                name: "ChaCha20-Poly1305",
+                algorithm: super::Algorithm::ChaCha20Poly1305,
+                key_id: None,
+                algorithm_name: None,
+                tamper: false,
+                want: Some(b"some data"),
+            },
+            #[cfg(feature = "chacha")]
+            Test {
+                name: "changed key ID ChaCha20-Poly1305",
+                algorithm: super::Algorithm::ChaCha20Poly1305,
+                key_id: Some("key2"),
+                algorithm_name: None,
+                tamper: false,
+                want: None,
+            },
+            #[cfg(feature = "chacha")]
+            Test {
+                name: "unknown algorithm ChaCha20-Poly1305",
+                algorithm: super::Algorithm::ChaCha20Poly1305,
+                key_id: None,
+                algorithm_name: Some("ChaCha8-Poly1305"),
+                tamper: false,
+                want: None,
+            },
+            #[cfg(feature = "chacha")]
+            Test {
+                name: "tampered ciphertext ChaCha20-Poly1305",
+                algorithm: super::Algorithm::ChaCha20Poly1305,
+                key_id: None,
+                algorithm_name: None,
+                tamper: true,
+                want: None,
+            },
+            #[cfg(all(feature = "aes-gcm", feature = "chacha"))]
+            Test {
+                name: "swapped algorithm AES-256-GCM",
+                algorithm: super::Algorithm::Aes256Gcm,
+                key_id: None,
+                algorithm_name: Some("ChaCha20-Poly1305"),
+                tamper: false,
+                want: None,
+            },
+            #[cfg(all(feature = "aes-gcm", feature = "chacha"))]
+            Test {
+                name: "swapped algorithm ChaCha20-Poly1305",
+                algorithm: super::Algorithm::ChaCha20Poly1305,
+                key_id: None,
+                algorithm_name: Some("AES-256-GCM"),
+                tamper: false,
+                want: None,
+            },
+        ];
+        // Both keys share the same key material, so that only the
+        // associated data tells the key IDs apart.
+        let key = [7u8; super::KEY_LEN];
+        let mut keys = super::KeyRing::new();
+        keys.current_key("key1", &key).key("key2", &key);
+        let keys = Arc::new(keys);
+        for t in &tests {
+            let encryption = super::Encryption::new(t.algorithm, keys.clone());
+            let (mut props, mut data) = encryption
+                .encrypt(lapin::BasicProperties::default(), b"some data")
+                .unwrap();
+            if let Some(key_id) = t.key_id {
+                props = crate::message::with_header(
+                    props,
+                    super::KEY_ID_HEADER,
+                    lapin::types::AMQPValue::LongString(key_id.into()),
+                );
+            }
+            if let Some(name) = t.algorithm_name {
+                props = crate::message::with_header(
+                    props,
+                    super::ALGORITHM_HEADER,
+                    lapin::types::AMQPValue::LongString(name.into()),
+                );
+            }
+            if t.tamper {
+                let last = data.len() - 1;
+                data[last] ^= 0xff;
+            }
+            let msg = crate::message::test_message(1, "ex", "key", props, &data);
+            let got = encryption.decrypt(&msg);
+            assert_eq!(t.want.map(|want| want.to_vec()), got, "{}", t.name);
+        }
+    }
+}
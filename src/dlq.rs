@@ -0,0 +1,283 @@
+// SPDX-License-Identifier: Apache-2.0 AND MIT
+//! `DeadLetterQueue` struct
+use lapin::options::{BasicAckOptions, BasicGetOptions, BasicNackOptions, BasicPublishOptions};
+
+/// The header name carrying the exchange the message was originally
+/// published to, set when the consumer dead-letters or retries the
+/// message itself.
+pub const ORIGIN_EXCHANGE_HEADER: &str = "x-origin-exchange";
+
+/// The header name carrying the routing key the message was originally
+/// published with, set when the consumer dead-letters or retries the
+/// message itself.
+pub const ORIGIN_ROUTING_KEY_HEADER: &str = "x-origin-routing-key";
+
+/// A dead-letter queue helper to list, inspect and replay the
+/// dead-lettered messages.
+///
+/// The dead-lettered messages carry the `x-death` header, which is
+/// available through the [Message::deaths] method.
+///
+/// [Message::deaths]: ../message/struct.Message.html#method.deaths
+pub struct DeadLetterQueue {
+    ch: lapin::Channel,
+    queue: String,
+}
+
+impl DeadLetterQueue {
+    pub async fn new(conn: &crate::Connection, queue: &str) -> crate::Result<Self> {
+        let ch = conn.channel().await?;
+        Ok(Self {
+            ch,
+            queue: queue.to_string(),
+        })
+    }
+    /// Returns up to `max` dead-lettered messages without removing those
+    /// from the queue.
+    pub async fn list(&self, max: usize) -> crate::Result<Vec<crate::Message>> {
+        let msgs = self.get(max).await?;
+        if let Some(last) = msgs.last() {
+            self.requeue(last.delivery_tag()).await?;
+        }
+        Ok(msgs)
+    }
+    /// Replays up to `max` dead-lettered messages back to the exchange
+    /// and the routing key those were originally published to, and
+    /// returns the number of the replayed messages.
+    ///
+    /// The messages without the origin, i.e. neither with the origin
+    /// headers nor with the `x-death` header, are left in the queue.
+    pub async fn replay(&self, max: usize) -> crate::Result<usize> {
+        let msgs = self.get(max).await?;
+        let mut replayed = 0;
+        for msg in &msgs {
+            let (ex, routing_key) = match origin(msg) {
+                Some(origin) => origin,
+                None => continue,
+            };
+            self.ch
+                .basic_publish(
+                    &ex,
+                    &routing_key,
+                    BasicPublishOptions::default(),
+                    msg.data().to_vec(),
+                    msg.properties().clone(),
+                )
+                .await
+                .map_err(crate::Error::from)?;
+            self.ch
+                .basic_ack(msg.delivery_tag(), BasicAckOptions::default())
+                .await
+                .map_err(crate::Error::from)?;
+            replayed += 1;
+        }
+        if replayed < msgs.len() {
+            if let Some(last) = msgs.last() {
+                self.requeue(last.delivery_tag()).await?;
+            }
+        }
+        Ok(replayed)
+    }
+    /// Gets up to `max` messages, leaving those unacknowledged.
+    async fn get(&self, max: usize) -> crate::Result<Vec<crate::Message>> {
+        let mut msgs = Vec::new();
+        while msgs.len() < max {
+            let msg = self
+                .ch
+                .basic_get(&self.queue, BasicGetOptions::default())
+                .await
+                .map_err(crate::Error::from)?;
+            match msg {
+                Some(msg) => msgs.push(crate::Message::new(msg.delivery)),
+                None => break,
+            }
+        }
+        Ok(msgs)
+    }
+    /// Requeues all the unacknowledged messages up to the `tag`.
+    async fn requeue(&self, tag: u64) -> crate::Result<()> {
+        let opts = BasicNackOptions {
+            multiple: true,
+            requeue: true,
+        };
+        self.ch
+            .basic_nack(tag, opts)
+            .await
+            .map_err(crate::Error::from)
+    }
+}
+
+/// Returns the exchange and the routing key the message was originally
+/// published to, based on the origin headers, or on the oldest `x-death`
+/// header entry other than the retry queues' ones, as those carry the
+/// retry queue name as the routing key.
+pub(crate) fn origin(msg: &crate::Message) -> Option<(String, String)> {
+    if let (Some(ex), Some(routing_key)) = (
+        msg.header::<String>(ORIGIN_EXCHANGE_HEADER),
+        msg.header::<String>(ORIGIN_ROUTING_KEY_HEADER),
+    ) {
+        return Some((ex, routing_key));
+    }
+    let death = msg
+        .deaths()
+        .into_iter()
+        .rev()
+        .find(|death| !crate::retry::is_retry_queue(&death.queue))?;
+    let routing_key = death.routing_keys.into_iter().next()?;
+    Some((death.exchange, routing_key))
+}
+
+/// Returns the properties to dead-letter the message with the error
+/// `reason`, as well as its origin.
+pub(crate) fn properties(
+    msg: &crate::Message,
+    props: lapin::BasicProperties,
+    reason: &str,
+) -> lapin::BasicProperties {
+    use crate::message::{with_header, ERROR_REASON_HEADER};
+    let props = with_header(
+        props,
+        ERROR_REASON_HEADER,
+        lapin::types::AMQPValue::LongString(reason.into()),
+    );
+    origin_properties(msg, props)
+}
+
+/// Returns the properties with the origin of the message, resolved by
+/// the [origin] function, or the exchange and the routing key the message
+/// is delivered with.
+///
+/// [origin]: fn.origin.html
+pub(crate) fn origin_properties(
+    msg: &crate::Message,
+    props: lapin::BasicProperties,
+) -> lapin::BasicProperties {
+    use crate::message::with_header;
+    use lapin::types::AMQPValue;
+    let (ex, routing_key) =
+        origin(msg).unwrap_or_else(|| (msg.exchange().to_string(), msg.routing_key().to_string()));
+    let props = with_header(
+        props,
+        ORIGIN_EXCHANGE_HEADER,
+        AMQPValue::LongString(ex.into()),
+    );
+    with_header(
+        props,
+        ORIGIN_ROUTING_KEY_HEADER,
+        AMQPValue::LongString(routing_key.into()),
+    )
+}
+
+/// Returns the queue arguments to dead-letter the messages to the
+/// `exchange` with the `routing_key`.
+pub(crate) fn arguments(
+    mut args: lapin::types::FieldTable,
+    exchange: &str,
+    routing_key: &str,
+) -> lapin::types::FieldTable {
+    args.insert(
+        "x-dead-letter-exchange".into(),
+        lapin::types::AMQPValue::LongString(exchange.into()),
+    );
+    args.insert(
+        "x-dead-letter-routing-key".into(),
+        lapin::types::AMQPValue::LongString(routing_key.into()),
+    );
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use lapin::types::{AMQPValue, FieldArray, FieldTable};
+    fn message(deaths: Vec<(&str, &str, &str)>, reason: Option<&str>) -> crate::Message {
+        let deaths: Vec<_> = deaths
+            .into_iter()
+            .map(|(queue, exchange, routing_key)| {
+                let mut death = FieldTable::default();
+                death.insert("queue".into(), AMQPValue::LongString(queue.into()));
+                death.insert("reason".into(), AMQPValue::LongString("rejected".into()));
+                death.insert("exchange".into(), AMQPValue::LongString(exchange.into()));
+                let keys = vec![AMQPValue::LongString(routing_key.into())];
+                death.insert(
+                    "routing-keys".into(),
+                    AMQPValue::FieldArray(FieldArray::from(keys)),
+                );
+                death.insert("count".into(), AMQPValue::LongLongInt(1));
+                AMQPValue::FieldTable(death)
+            })
+            .collect();
+        let mut headers = FieldTable::default();
+        if !deaths.is_empty() {
+            headers.insert(
+                "x-death".into(),
+                AMQPValue::FieldArray(FieldArray::from(deaths)),
+            );
+        }
+        let mut props = lapin::BasicProperties::default().with_headers(headers);
+        if let Some(reason) = reason {
+            let origin = lapin::BasicProperties::default();
+            let origin = crate::message::test_message(1, "ex", "key", origin, b"");
+            props = super::properties(&origin, props, reason);
+        }
+        crate::message::test_message(1, "dlx", "dlq", props, b"")
+    }
+    #[test]
+    fn origin() {
+        struct Test {
+            name: &'static str,
+            deaths: Vec<(&'static str, &'static str, &'static str)>,
+            reason: Option<&'static str>,
+            want: Option<(&'static str, &'static str)>,
+        }
+        let tests = [
+            Test {
+                name: "not dead-lettered",
+                deaths: vec![],
+                reason: None,
+                want: None,
+            },
+            Test {
+                name: "rejected once",
+                deaths: vec![("q", "ex", "key")],
+                reason: None,
+                want: Some(("ex", "key")),
+            },
+            Test {
+                name: "rejected through the default exchange",
+                deaths: vec![("q", "", "q")],
+                reason: None,
+                want: Some(("", "q")),
+            },
+            Test {
+                name: "oldest entry",
+                deaths: vec![("q2", "ex2", "key2"), ("q1", "ex1", "key1")],
+                reason: None,
+                want: Some(("ex1", "key1")),
+            },
+            Test {
+                name: "retried by the broker only",
+                deaths: vec![("q", "", "q"), ("q.retry.100", "", "q.retry.100")],
+                reason: None,
+                want: Some(("", "q")),
+            },
+            Test {
+                name: "retried and then dead-lettered",
+                deaths: vec![("q", "", "q"), ("q.retry.100", "", "q.retry.100")],
+                reason: Some("retried"),
+                want: Some(("ex", "key")),
+            },
+            Test {
+                name: "dead-lettered by the consumer",
+                deaths: vec![],
+                reason: Some("invalid payload"),
+                want: Some(("ex", "key")),
+            },
+        ];
+        for t in &tests {
+            let msg = message(t.deaths.clone(), t.reason);
+            let got = super::origin(&msg);
+            let want = t.want.map(|(ex, key)| (ex.to_string(), key.to_string()));
+            assert_eq!(want, got, "{}", t.name);
+        }
+    }
+}
@@ -7,6 +7,14 @@ pub enum Error {
     ///
     /// [lapin::Error]: https://docs.rs/lapin/latest/lapin/enum.Error.html
     Internal(lapin::Error),
+    /// Payload encryption error variant.
+    Crypto(String),
+    /// Message payload encoding or decoding error variant.
+    Codec(String),
+    /// [RemoteError] variant returned by the RPC server.
+    ///
+    /// [RemoteError]: ../rpc/struct.RemoteError.html
+    Remote(crate::rpc::RemoteError),
     /// Other error variant.
     Other,
 }
@@ -15,6 +23,9 @@ impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
             Self::Internal(err) => Some(err),
+            Self::Crypto(_) => None,
+            Self::Codec(_) => None,
+            Self::Remote(_) => None,
             Self::Other => None,
         }
     }
@@ -24,6 +35,9 @@ impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Internal(err) => err.fmt(f),
+            Self::Crypto(msg) => write!(f, "crypto error: {}", msg),
+            Self::Codec(msg) => write!(f, "codec error: {}", msg),
+            Self::Remote(err) => err.fmt(f),
             Self::Other => write!(f, "other error"),
         }
     }
@@ -33,6 +47,9 @@ impl std::fmt::Debug for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Internal(err) => err.fmt(f),
+            Self::Crypto(msg) => write!(f, "Error::Crypto({:?})", msg),
+            Self::Codec(msg) => write!(f, "Error::Codec({:?})", msg),
+            Self::Remote(err) => write!(f, "Error::Remote({:?})", err),
             Self::Other => write!(f, "Error::Other"),
         }
     }
@@ -51,6 +68,18 @@ impl std::cmp::PartialEq for Error {
                 Self::Internal(other) => Self::eq_internal(err, other),
                 _ => false,
             },
+            Self::Crypto(msg) => match other {
+                Self::Crypto(other) => msg == other,
+                _ => false,
+            },
+            Self::Codec(msg) => match other {
+                Self::Codec(other) => msg == other,
+                _ => false,
+            },
+            Self::Remote(err) => match other {
+                Self::Remote(other) => err == other,
+                _ => false,
+            },
             Self::Other => match other {
                 Self::Other => true,
                 _ => false,
@@ -0,0 +1,460 @@
+// SPDX-License-Identifier: Apache-2.0 AND MIT
+//! `FromMessage` and `IntoReply` traits for the async fn handlers
+use futures::future::{self, BoxFuture, Future};
+use std::marker::PhantomData;
+
+/// A handler argument extractor from the [Message] and the shared state.
+///
+/// [Message]: ../message/struct.Message.html
+pub trait FromMessage<S>: Sized {
+    fn from_message(msg: &crate::Message, state: &S) -> Result<Self, crate::MessageError>;
+}
+
+/// A handler return value conversion to the [Reply], or to the
+/// [MessageError] action.
+///
+/// [Reply]: ../message/struct.Reply.html
+/// [MessageError]: ../message/enum.MessageError.html
+pub trait IntoReply {
+    fn into_reply(self) -> Result<crate::Reply, crate::MessageError>;
+}
+
+/// An async fn handler, taking up to four [FromMessage] arguments and
+/// returning the [IntoReply] value.
+///
+/// It's implemented for the functions of the supported signatures only,
+/// so that the unsupported ones are the compile-time errors:
+///
+/// ```compile_fail
+/// use async_mq::handler::FnProcessor;
+///
+/// async fn handler(_: u32) {}
+///
+/// let processor = FnProcessor::new(handler);
+/// ```
+///
+/// [FromMessage]: trait.FromMessage.html
+/// [IntoReply]: trait.IntoReply.html
+pub trait Handler<T, S>: Clone + Send + Sync + 'static {
+    fn call(
+        &self,
+        msg: &crate::Message,
+        state: &S,
+    ) -> BoxFuture<'static, Result<crate::Reply, crate::MessageError>>;
+}
+
+macro_rules! impl_handler {
+    ($($t:ident),*) => {
+        impl<F, Fut, R, S, $($t,)*> Handler<($($t,)*), S> for F
+        where
+            F: Fn($($t),*) -> Fut + Clone + Send + Sync + 'static,
+            Fut: Future<Output = R> + Send + 'static,
+            R: IntoReply,
+            $($t: FromMessage<S>,)*
+        {
+            #[allow(non_snake_case, unused_variables)]
+            fn call(
+                &self,
+                msg: &crate::Message,
+                state: &S,
+            ) -> BoxFuture<'static, Result<crate::Reply, crate::MessageError>> {
+                $(let $t = match $t::from_message(msg, state) {
+                    Ok(v) => v,
+                    Err(err) => return Box::pin(future::ready(Err(err))),
+                };)*
+                let fut = (self)($($t),*);
+                Box::pin(async move { fut.await.into_reply() })
+            }
+        }
+    };
+}
+
+impl_handler!();
+impl_handler!(T1);
+impl_handler!(T1, T2);
+impl_handler!(T1, T2, T3);
+impl_handler!(T1, T2, T3, T4);
+
+/// A [MessageProcess] implementation over the async fn [Handler], with
+/// the shared state.
+///
+/// # Examples
+///
+/// ```no_run
+/// use async_mq::handler::{FnProcessor, RoutingKey, State};
+///
+/// #[derive(Clone)]
+/// struct Config {
+///     prefix: String,
+/// }
+///
+/// async fn hello(RoutingKey(key): RoutingKey, State(config): State<Config>) -> String {
+///     format!("{} {}", config.prefix, key)
+/// }
+///
+/// let config = Config {
+///     prefix: String::from("hello"),
+/// };
+/// let processor = FnProcessor::with_state(hello, config);
+/// ```
+///
+/// [MessageProcess]: ../message/trait.MessageProcess.html
+/// [Handler]: trait.Handler.html
+pub struct FnProcessor<H, T, S> {
+    handler: H,
+    state: S,
+    _args: PhantomData<fn() -> T>,
+}
+
+impl<H, T, S: Clone> Clone for FnProcessor<H, T, S>
+where
+    H: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            handler: self.handler.clone(),
+            state: self.state.clone(),
+            _args: PhantomData,
+        }
+    }
+}
+
+impl<H, T> FnProcessor<H, T, ()>
+where
+    H: Handler<T, ()>,
+{
+    pub fn new(handler: H) -> Self {
+        Self::with_state(handler, ())
+    }
+}
+
+impl<H, T, S> FnProcessor<H, T, S>
+where
+    H: Handler<T, S>,
+{
+    /// Returns the processor with the `state` shared through the [State]
+    /// extractor.
+    ///
+    /// [State]: struct.State.html
+    pub fn with_state(handler: H, state: S) -> Self {
+        Self {
+            handler,
+            state,
+            _args: PhantomData,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<H, T, S> crate::MessageProcess for FnProcessor<H, T, S>
+where
+    H: Handler<T, S>,
+    T: 'static,
+    S: Clone + Send + Sync + 'static,
+{
+    async fn process(&self, msg: &crate::Message) -> Result<Vec<u8>, crate::MessageError> {
+        self.process_reply(msg)
+            .await
+            .map(|reply| reply.data().to_vec())
+    }
+    async fn process_reply(
+        &self,
+        msg: &crate::Message,
+    ) -> Result<crate::Reply, crate::MessageError> {
+        self.handler.call(msg, &self.state).await
+    }
+}
+
+/// The raw message payload extractor.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Data(pub Vec<u8>);
+
+impl<S> FromMessage<S> for Data {
+    fn from_message(msg: &crate::Message, _: &S) -> Result<Self, crate::MessageError> {
+        Ok(Self(msg.data().to_vec()))
+    }
+}
+
+/// The typed message payload extractor, decoded by the `C` [Codec].
+///
+/// The message failed to decode is rejected with the
+/// [Status::BAD_REQUEST] status.  As the return value, it's encoded
+/// by the `C` [Codec] with its content type.
+///
+/// [Codec]: ../codec/trait.Codec.html
+/// [Status::BAD_REQUEST]: ../rpc/struct.Status.html#associatedconstant.BAD_REQUEST
+#[derive(Clone, Debug, PartialEq)]
+pub struct Body<T, C>(pub T, pub PhantomData<C>);
+
+impl<T, C> Body<T, C> {
+    pub fn new(value: T) -> Self {
+        Self(value, PhantomData)
+    }
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T, C, S> FromMessage<S> for Body<T, C>
+where
+    C: crate::Codec<T> + Default,
+{
+    fn from_message(msg: &crate::Message, _: &S) -> Result<Self, crate::MessageError> {
+        C::default()
+            .decode(msg.data())
+            .map(Self::new)
+            .map_err(|err| {
+                crate::MessageError::reject()
+                    .with_status(crate::rpc::Status::BAD_REQUEST)
+                    .with_reason(&err.to_string())
+            })
+    }
+}
+
+impl<T, C> IntoReply for Body<T, C>
+where
+    C: crate::Codec<T> + Default,
+{
+    fn into_reply(self) -> Result<crate::Reply, crate::MessageError> {
+        let codec = C::default();
+        match codec.encode(&self.0) {
+            Ok(data) => Ok(crate::Reply::new(data).with_content_type(codec.content_type())),
+            Err(err) => Err(crate::MessageError::reject().with_reason(&err.to_string())),
+        }
+    }
+}
+
+/// The message headers extractor.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Headers(lapin::types::FieldTable);
+
+impl Headers {
+    /// Returns the `key` header value, if any.
+    pub fn get<T: crate::FromHeader>(&self, key: &str) -> Option<T> {
+        self.0
+            .inner()
+            .get(&lapin::types::ShortString::from(key))
+            .and_then(T::from_header)
+    }
+    /// Returns true if the message carries the `key` header.
+    pub fn contains(&self, key: &str) -> bool {
+        self.0
+            .inner()
+            .contains_key(&lapin::types::ShortString::from(key))
+    }
+    /// Returns the header names.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.0.inner().keys().map(|key| key.as_str())
+    }
+    pub fn len(&self) -> usize {
+        self.0.inner().len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.inner().is_empty()
+    }
+}
+
+impl<S> FromMessage<S> for Headers {
+    fn from_message(msg: &crate::Message, _: &S) -> Result<Self, crate::MessageError> {
+        let headers = msg.properties().headers().clone().unwrap_or_default();
+        Ok(Self(headers))
+    }
+}
+
+/// The message routing key extractor.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RoutingKey(pub String);
+
+impl<S> FromMessage<S> for RoutingKey {
+    fn from_message(msg: &crate::Message, _: &S) -> Result<Self, crate::MessageError> {
+        Ok(Self(msg.routing_key().to_string()))
+    }
+}
+
+/// The shared state extractor.
+#[derive(Clone, Debug, PartialEq)]
+pub struct State<S>(pub S);
+
+impl<S: Clone> FromMessage<S> for State<S> {
+    fn from_message(_: &crate::Message, state: &S) -> Result<Self, crate::MessageError> {
+        Ok(Self(state.clone()))
+    }
+}
+
+impl<T: FromMessage<S>, S> FromMessage<S> for Option<T> {
+    fn from_message(msg: &crate::Message, state: &S) -> Result<Self, crate::MessageError> {
+        Ok(T::from_message(msg, state).ok())
+    }
+}
+
+/// Acknowledges the message without the reply payload.
+///
+/// The request carrying the reply-to queue is still replied to, with the
+/// empty payload, so that the RPC caller doesn't wait for the reply
+/// forever.
+impl IntoReply for () {
+    fn into_reply(self) -> Result<crate::Reply, crate::MessageError> {
+        Ok(crate::Reply::default())
+    }
+}
+
+impl IntoReply for crate::Reply {
+    fn into_reply(self) -> Result<crate::Reply, crate::MessageError> {
+        Ok(self)
+    }
+}
+
+impl IntoReply for Vec<u8> {
+    fn into_reply(self) -> Result<crate::Reply, crate::MessageError> {
+        Ok(crate::Reply::new(self))
+    }
+}
+
+impl IntoReply for String {
+    fn into_reply(self) -> Result<crate::Reply, crate::MessageError> {
+        Ok(crate::Reply::new(self.into_bytes()).with_content_type("text/plain"))
+    }
+}
+
+impl IntoReply for &'static str {
+    fn into_reply(self) -> Result<crate::Reply, crate::MessageError> {
+        String::from(self).into_reply()
+    }
+}
+
+impl IntoReply for Data {
+    fn into_reply(self) -> Result<crate::Reply, crate::MessageError> {
+        Ok(crate::Reply::new(self.0))
+    }
+}
+
+/// Settles the message with the error action.
+impl IntoReply for crate::MessageError {
+    fn into_reply(self) -> Result<crate::Reply, crate::MessageError> {
+        Err(self)
+    }
+}
+
+impl<T, E> IntoReply for Result<T, E>
+where
+    T: IntoReply,
+    E: Into<crate::MessageError>,
+{
+    fn into_reply(self) -> Result<crate::Reply, crate::MessageError> {
+        self.map_err(Into::into).and_then(IntoReply::into_reply)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MessageError;
+    use std::sync::Arc;
+    #[derive(Clone, Copy, Debug, Default)]
+    struct Plain;
+    impl crate::Codec<String> for Plain {
+        fn content_type(&self) -> &str {
+            "text/plain"
+        }
+        fn encode(&self, value: &String) -> crate::Result<Vec<u8>> {
+            Ok(value.as_bytes().to_vec())
+        }
+        fn decode(&self, data: &[u8]) -> crate::Result<String> {
+            String::from_utf8(data.to_vec()).map_err(|err| crate::Error::Codec(err.to_string()))
+        }
+    }
+    fn message(routing_key: &str, data: &[u8]) -> crate::Message {
+        let props = crate::message::with_header(
+            lapin::BasicProperties::default(),
+            "x-count",
+            lapin::types::AMQPValue::LongUInt(2),
+        );
+        crate::message::test_message(1, "", routing_key, props, data)
+    }
+    async fn none() {}
+    async fn echo(Data(data): Data) -> Vec<u8> {
+        data
+    }
+    async fn greet(Body(name, _): Body<String, Plain>, State(greeting): State<String>) -> String {
+        format!("{} {}", greeting, name)
+    }
+    async fn count(
+        RoutingKey(key): RoutingKey,
+        headers: Headers,
+        State(_): State<String>,
+    ) -> Result<Body<String, Plain>, MessageError> {
+        match headers.get::<u32>("x-count") {
+            Some(count) => Ok(Body::new(key.repeat(count as usize))),
+            None => Err(MessageError::Drop),
+        }
+    }
+    async fn fail() -> MessageError {
+        MessageError::nack().with_reason("failed")
+    }
+    #[test]
+    fn headers() {
+        let headers = Headers::from_message(&message("key", b""), &()).unwrap();
+        assert_eq!(Some(2), headers.get::<u32>("x-count"));
+        assert_eq!(None, headers.get::<u32>("x-other"));
+        assert!(headers.contains("x-count"));
+        assert!(!headers.contains("x-other"));
+        assert_eq!(vec!["x-count"], headers.keys().collect::<Vec<_>>());
+        assert_eq!(1, headers.len());
+        assert!(Headers::default().is_empty());
+    }
+    #[test]
+    fn process_reply() {
+        struct Test {
+            name: &'static str,
+            processor: Arc<dyn crate::MessageProcess + Send + Sync>,
+            data: &'static [u8],
+            want: Result<crate::Reply, MessageError>,
+        }
+        let state = String::from("hello");
+        let tests = vec![
+            Test {
+                name: "no arguments and no reply",
+                processor: Arc::new(FnProcessor::new(none)),
+                data: b"",
+                want: Ok(crate::Reply::default()),
+            },
+            Test {
+                name: "raw data",
+                processor: Arc::new(FnProcessor::new(echo)),
+                data: b"data",
+                want: Ok(crate::Reply::new(b"data".to_vec())),
+            },
+            Test {
+                name: "typed body and state",
+                processor: Arc::new(FnProcessor::with_state(greet, state.clone())),
+                data: b"world",
+                want: Ok(crate::Reply::new(b"hello world".to_vec()).with_content_type("text/plain")),
+            },
+            Test {
+                name: "typed body decode failure",
+                processor: Arc::new(FnProcessor::with_state(greet, state.clone())),
+                data: &[0xff],
+                want: Err(MessageError::reject()
+                    .with_status(crate::rpc::Status::BAD_REQUEST)
+                    .with_reason("codec error: invalid utf-8 sequence of 1 bytes from index 0")),
+            },
+            Test {
+                name: "routing key, headers and typed reply",
+                processor: Arc::new(FnProcessor::with_state(count, state)),
+                data: b"",
+                want: Ok(crate::Reply::new(b"keykey".to_vec()).with_content_type("text/plain")),
+            },
+            Test {
+                name: "error action",
+                processor: Arc::new(FnProcessor::new(fail)),
+                data: b"",
+                want: Err(MessageError::nack().with_reason("failed")),
+            },
+        ];
+        for t in tests {
+            let msg = message("key", t.data);
+            let got = futures::executor::block_on(t.processor.process_reply(&msg));
+            assert_eq!(t.want, got, "{}", t.name);
+        }
+    }
+}
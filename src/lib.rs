@@ -3,17 +3,43 @@
 //!
 //! [lapin]: https://crates.io/crates/lapin
 //! [amqp]: https://www.amqp.org
+pub use ack::Acker;
 pub use client::{Client, Connection};
-pub use consume::{Consumer, ConsumerBuilder};
+pub use codec::Codec;
+pub use consume::{Bindings, Consumer, ConsumerBuilder, ConsumerHandle};
+pub use dlq::DeadLetterQueue;
 pub use error::Error;
-pub use message::{Message, MessageError, MessagePeek, MessageProcess};
+pub use message::{Death, FromHeader, Message, MessageError, MessagePeek, MessageProcess, Reply};
 pub use produce::{Producer, ProducerBuilder};
+pub use retry::RetryPolicy;
+pub use rpc::{RemoteError, Status};
+pub use sign::{Signer, Verifier};
 
+pub mod ack;
+pub mod batch;
+pub mod chunk;
 pub mod client;
+pub mod codec;
 pub mod consume;
+#[cfg(feature = "encryption")]
+pub mod crypto;
+pub mod dlq;
 pub mod error;
+pub mod handler;
 pub mod message;
+pub mod middleware;
+pub mod multi;
 pub mod produce;
+pub mod retry;
+pub mod route;
+pub mod rpc;
+pub mod schema;
+#[cfg(feature = "tower")]
+pub mod service;
+pub mod sign;
+pub mod typed;
+#[cfg(feature = "json-schema")]
+pub mod validate;
 
 /// Crate local type aliases for less typing.  Those are meant for the
 /// internal use cases and won't be published.
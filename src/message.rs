@@ -1,82 +1,702 @@
 // SPDX-License-Identifier: Apache-2.0 AND MIT
 //! `Message` struct, `MessagePeek` and `MessageProcess` trait
 use async_trait::async_trait;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-/// A zero-cost [lapin::message::Delivery] [newtype].
+/// A [lapin::message::Delivery] wrapper with the optional [Acker]
+/// acknowledgement handle.
 ///
 /// [lapin::message::Delivery]: https://docs.rs/lapin/latest/lapin/message/struct.Delivery.html
-/// [newtype]: https://doc.rust-lang.org/1.0.0/style/features/types/newtype.html
-pub struct Message(lapin::message::Delivery);
+/// [Acker]: ../ack/struct.Acker.html
+pub struct Message {
+    delivery: lapin::message::Delivery,
+    acker: Option<crate::Acker>,
+    taken: bool,
+    chunks: Vec<u64>,
+}
+
+/// An `x-death` header entry, added by the broker when the message is
+/// dead-lettered.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Death {
+    /// The queue the message was dead-lettered from.
+    pub queue: String,
+    /// The reason of the dead-lettering, e.g. `rejected` or `expired`.
+    pub reason: String,
+    /// The exchange the message was originally published to.
+    pub exchange: String,
+    /// The routing keys the message was originally published with.
+    pub routing_keys: Vec<String>,
+    /// The number of times the message was dead-lettered from the queue
+    /// for the reason.
+    pub count: u64,
+}
+
+impl Death {
+    fn from_table(table: &lapin::types::FieldTable) -> Self {
+        let get = |key: &str| table.inner().get(&lapin::types::ShortString::from(key));
+        let string = |key: &str| {
+            get(key)
+                .and_then(header_str)
+                .map(String::from)
+                .unwrap_or_default()
+        };
+        let routing_keys = match get("routing-keys") {
+            Some(lapin::types::AMQPValue::FieldArray(keys)) => keys
+                .as_slice()
+                .iter()
+                .filter_map(header_str)
+                .map(String::from)
+                .collect(),
+            _ => vec![],
+        };
+        Self {
+            queue: string("queue"),
+            reason: string("reason"),
+            exchange: string("exchange"),
+            routing_keys,
+            count: get("count").and_then(header_u64).unwrap_or_default(),
+        }
+    }
+}
+
+/// The header name carrying the error reason of the error reply.
+pub const ERROR_REASON_HEADER: &str = "x-error-reason";
 
 /// Error actions used both by [MessagePeek] and [MessageProcess]
 /// trait implementations.
 ///
+/// Except for the [Drop], the `reply` payload, or the empty payload in
+/// case of no `reply` is provided, is sent back to the `reply_to` queue
+/// with the status and the `reason` in the `x-error-reason` header, so that
+/// the RPC callers won't wait for the reply forever.
+///
+/// [Drop]: #variant.Drop
+///
 /// [MessagePeek]: trait.MessagePeek.html
 /// [MessageProcess]: trait.MessageProcess.html
+#[derive(Clone, Debug, PartialEq)]
 pub enum MessageError {
-    /// Silently drop a message.
+    /// Silently drop a message, by acknowledging it without the reply.
     Drop,
     /// Reject a message.
-    Reject,
+    Reject {
+        requeue: bool,
+        status: Option<crate::rpc::Status>,
+        reason: Option<String>,
+        reply: Option<Vec<u8>>,
+    },
     /// Nack a message.
-    Nack,
+    ///
+    /// The `multiple` flag nacks all the unacknowledged messages up to the
+    /// message.  The [Consumer] honors it only when the message is the
+    /// only unsettled one, not to nack the ones in-flight, and never for
+    /// the reassembled messages nor the ones handed out with the [Acker].
+    ///
+    /// [Acker]: ../ack/struct.Acker.html
+    ///
+    /// [Consumer]: ../consume/struct.Consumer.html
+    Nack {
+        requeue: bool,
+        multiple: bool,
+        status: Option<crate::rpc::Status>,
+        reason: Option<String>,
+        reply: Option<Vec<u8>>,
+    },
+}
+
+impl MessageError {
+    /// Returns the reject action without requeue.
+    pub fn reject() -> Self {
+        Self::Reject {
+            requeue: false,
+            status: None,
+            reason: None,
+            reply: None,
+        }
+    }
+    /// Returns the nack action without requeue.
+    pub fn nack() -> Self {
+        Self::Nack {
+            requeue: false,
+            multiple: false,
+            status: None,
+            reason: None,
+            reply: None,
+        }
+    }
+    /// Requeue the message.
+    pub fn with_requeue(mut self, requeue: bool) -> Self {
+        match &mut self {
+            Self::Drop => {}
+            Self::Reject { requeue: r, .. } | Self::Nack { requeue: r, .. } => *r = requeue,
+        }
+        self
+    }
+    /// Nack all the unacknowledged messages up to the message.
+    pub fn with_multiple(mut self, multiple: bool) -> Self {
+        if let Self::Nack { multiple: m, .. } = &mut self {
+            *m = multiple;
+        }
+        self
+    }
+    /// Provide the RPC status code of the error reply.
+    ///
+    /// It's the [Status::INTERNAL_ERROR] by default.
+    ///
+    /// [Status::INTERNAL_ERROR]: ../rpc/struct.Status.html#associatedconstant.INTERNAL_ERROR
+    pub fn with_status(mut self, status: crate::rpc::Status) -> Self {
+        match &mut self {
+            Self::Drop => {}
+            Self::Reject { status: s, .. } | Self::Nack { status: s, .. } => *s = Some(status),
+        }
+        self
+    }
+    /// Provide the error reason.
+    pub fn with_reason(mut self, reason: &str) -> Self {
+        match &mut self {
+            Self::Drop => {}
+            Self::Reject { reason: r, .. } | Self::Nack { reason: r, .. } => {
+                *r = Some(reason.to_string())
+            }
+        }
+        self
+    }
+    /// Provide the error reply payload.
+    pub fn with_reply(mut self, reply: Vec<u8>) -> Self {
+        match &mut self {
+            Self::Drop => {}
+            Self::Reject { reply: r, .. } | Self::Nack { reply: r, .. } => *r = Some(reply),
+        }
+        self
+    }
+    /// Returns the error reason.
+    pub fn reason(&self) -> Option<&str> {
+        match self {
+            Self::Drop => None,
+            Self::Reject { reason, .. } | Self::Nack { reason, .. } => reason.as_deref(),
+        }
+    }
+    /// Returns true in case the message is requeued, which makes the
+    /// error retriable.
+    pub fn requeue(&self) -> bool {
+        match self {
+            Self::Drop => false,
+            Self::Reject { requeue, .. } | Self::Nack { requeue, .. } => *requeue,
+        }
+    }
+    /// Returns the properties with the error reply envelope set.
+    pub(crate) fn properties(&self, props: lapin::BasicProperties) -> lapin::BasicProperties {
+        let status = match self {
+            Self::Drop => None,
+            Self::Reject { status, .. } | Self::Nack { status, .. } => *status,
+        };
+        let status = status.unwrap_or(crate::rpc::Status::INTERNAL_ERROR);
+        crate::rpc::envelope(props, status, self.reason())
+    }
+    /// Returns the error reply payload, or the empty payload in case
+    /// of no reply is provided, except for the [Drop].
+    ///
+    /// [Drop]: #variant.Drop
+    pub(crate) fn reply(&self) -> Option<Vec<u8>> {
+        match self {
+            Self::Drop => None,
+            Self::Reject { reply, .. } | Self::Nack { reply, .. } => {
+                Some(reply.clone().unwrap_or_default())
+            }
+        }
+    }
+}
+
+/// Maps the boxed error, e.g. the error of the tower middleware, to the
+/// rejection with the [Status::INTERNAL_ERROR] status and the error as
+/// the reason.
+///
+/// [Status::INTERNAL_ERROR]: ../rpc/struct.Status.html#associatedconstant.INTERNAL_ERROR
+impl From<Box<dyn std::error::Error + Send + Sync>> for MessageError {
+    fn from(err: Box<dyn std::error::Error + Send + Sync>) -> Self {
+        Self::reject()
+            .with_status(crate::rpc::Status::INTERNAL_ERROR)
+            .with_reason(&err.to_string())
+    }
+}
+
+/// A reply of the [MessageProcess], carrying the reply properties and
+/// headers along with the body.
+///
+/// [MessageProcess]: trait.MessageProcess.html
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Reply {
+    data: Vec<u8>,
+    content_type: Option<String>,
+    content_encoding: Option<String>,
+    status: Option<crate::rpc::Status>,
+    kind: Option<String>,
+    headers: Vec<(String, String)>,
+}
+
+impl Reply {
+    pub fn new(data: Vec<u8>) -> Self {
+        Self {
+            data,
+            ..Default::default()
+        }
+    }
+    #[inline]
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+    /// Specify the content type, overriding the request's one.
+    pub fn with_content_type(mut self, content_type: &str) -> Self {
+        self.content_type = Some(content_type.to_string());
+        self
+    }
+    /// Specify the content encoding, overriding the request's one.
+    pub fn with_content_encoding(mut self, content_encoding: &str) -> Self {
+        self.content_encoding = Some(content_encoding.to_string());
+        self
+    }
+    /// Specify the RPC status code.
+    ///
+    /// It's the [Status::OK] by default.
+    ///
+    /// [Status::OK]: ../rpc/struct.Status.html#associatedconstant.OK
+    pub fn with_status(mut self, status: crate::rpc::Status) -> Self {
+        self.status = Some(status);
+        self
+    }
+    /// Specify the message type, the AMQP `type` property.
+    pub fn with_kind(mut self, kind: &str) -> Self {
+        self.kind = Some(kind.to_string());
+        self
+    }
+    /// Add the string header.
+    pub fn with_header(mut self, key: &str, value: &str) -> Self {
+        self.headers.push((key.to_string(), value.to_string()));
+        self
+    }
+    /// Returns the properties with the reply properties, headers and the
+    /// reply envelope set.
+    pub(crate) fn properties(&self, props: lapin::BasicProperties) -> lapin::BasicProperties {
+        let status = self.status.unwrap_or(crate::rpc::Status::OK);
+        let mut props = crate::rpc::envelope(props, status, None);
+        if let Some(content_type) = &self.content_type {
+            props = props.with_content_type(content_type.as_str().into());
+        }
+        if let Some(content_encoding) = &self.content_encoding {
+            props = props.with_content_encoding(content_encoding.as_str().into());
+        }
+        if let Some(kind) = &self.kind {
+            props = props.with_type(kind.as_str().into());
+        }
+        for (key, value) in &self.headers {
+            props = with_header(
+                props,
+                key,
+                lapin::types::AMQPValue::LongString(value.as_str().into()),
+            );
+        }
+        props
+    }
+}
+
+impl From<Vec<u8>> for Reply {
+    fn from(data: Vec<u8>) -> Self {
+        Self::new(data)
+    }
 }
 
 impl Message {
     #[inline]
     pub fn new(delivery: lapin::message::Delivery) -> Self {
-        Self(delivery)
+        Self {
+            delivery,
+            acker: None,
+            taken: false,
+            chunks: Vec::new(),
+        }
+    }
+    /// Returns the message with the [Acker] handle of the channel the
+    /// message is delivered over.
+    ///
+    /// [Acker]: ../ack/struct.Acker.html
+    pub(crate) fn with_acker(delivery: lapin::message::Delivery, ch: lapin::Channel) -> Self {
+        let mut msg = Self::new(delivery);
+        msg.set_acker(ch);
+        msg
+    }
+    /// Sets the [Acker] handle of the channel the message, as well as
+    /// the other chunks, is delivered over.
+    ///
+    /// [Acker]: ../ack/struct.Acker.html
+    pub(crate) fn set_acker(&mut self, ch: lapin::Channel) {
+        let acker = crate::Acker::new(ch, self.delivery_tag(), self.chunks.clone());
+        self.acker = Some(acker);
+    }
+    /// Sets the delivery tags of the other chunks of the reassembled
+    /// message, settled along with the message.
+    pub(crate) fn set_chunks(&mut self, tags: Vec<u64>) {
+        self.chunks = tags;
+    }
+    /// Returns the delivery tags of the other chunks of the reassembled
+    /// message.
+    #[inline]
+    pub(crate) fn chunks(&self) -> &[u64] {
+        &self.chunks
+    }
+    /// Returns the copy of the message without the [Acker], as it's
+    /// settled through the original one.
+    ///
+    /// [Acker]: ../ack/struct.Acker.html
+    #[cfg(feature = "tower")]
+    pub(crate) fn detach(&self) -> Self {
+        Self::new(self.delivery.clone())
     }
     #[inline]
     pub fn data(&self) -> &[u8] {
-        &self.0.data
+        &self.delivery.data
+    }
+    #[inline]
+    pub(crate) fn set_data(&mut self, data: Vec<u8>) {
+        self.delivery.data = data;
+    }
+    #[inline]
+    pub(crate) fn set_header(&mut self, key: &str, value: lapin::types::AMQPValue) {
+        let props = std::mem::take(&mut self.delivery.properties);
+        self.delivery.properties = with_header(props, key, value);
     }
     #[inline]
     pub fn delivery_tag(&self) -> u64 {
-        self.0.delivery_tag
+        self.delivery.delivery_tag
     }
     #[inline]
     pub fn reply_to(&self) -> Option<&str> {
-        self.0
+        short_str(self.delivery.properties.reply_to())
+    }
+    /// Returns the exchange name the message was published to.
+    #[inline]
+    pub fn exchange(&self) -> &str {
+        self.delivery.exchange.as_str()
+    }
+    #[inline]
+    pub fn routing_key(&self) -> &str {
+        self.delivery.routing_key.as_str()
+    }
+    /// Returns true in case the message was delivered before.
+    #[inline]
+    pub fn redelivered(&self) -> bool {
+        self.delivery.redelivered
+    }
+    #[inline]
+    pub fn correlation_id(&self) -> Option<&str> {
+        short_str(self.delivery.properties.correlation_id())
+    }
+    #[inline]
+    pub fn message_id(&self) -> Option<&str> {
+        short_str(self.delivery.properties.message_id())
+    }
+    #[inline]
+    pub fn content_type(&self) -> Option<&str> {
+        short_str(self.delivery.properties.content_type())
+    }
+    #[inline]
+    pub fn content_encoding(&self) -> Option<&str> {
+        short_str(self.delivery.properties.content_encoding())
+    }
+    /// Returns the message type, the `type` property.
+    #[inline]
+    pub fn kind(&self) -> Option<&str> {
+        short_str(self.delivery.properties.kind())
+    }
+    /// Returns the timestamp, carried in seconds since the Unix epoch.
+    pub fn timestamp(&self) -> Option<SystemTime> {
+        self.delivery
             .properties
-            .reply_to()
-            .as_ref()
-            .map(|str| str.as_str())
+            .timestamp()
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+    }
+    #[inline]
+    pub fn priority(&self) -> Option<u8> {
+        *self.delivery.properties.priority()
+    }
+    /// Returns the per-message TTL, in case it's a valid milliseconds.
+    pub fn expiration(&self) -> Option<Duration> {
+        short_str(self.delivery.properties.expiration())
+            .and_then(|ms| ms.parse().ok())
+            .map(Duration::from_millis)
+    }
+    #[inline]
+    pub fn app_id(&self) -> Option<&str> {
+        short_str(self.delivery.properties.app_id())
+    }
+    #[inline]
+    pub fn user_id(&self) -> Option<&str> {
+        short_str(self.delivery.properties.user_id())
+    }
+    /// Returns the header names.
+    pub fn header_names(&self) -> Vec<&str> {
+        match self.delivery.properties.headers() {
+            Some(headers) => headers.inner().keys().map(|key| key.as_str()).collect(),
+            None => vec![],
+        }
+    }
+    /// Returns the header value of the `key` converted to `T`, or `None`
+    /// in case the header is missing or it's not convertible.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// fn retry_count(msg: &async_mq::Message) -> u32 {
+    ///     msg.header::<u32>("x-retry-count").unwrap_or_default()
+    /// }
+    /// ```
+    pub fn header<T: FromHeader>(&self, key: &str) -> Option<T> {
+        self.header_value(key).and_then(T::from_header)
+    }
+    /// Takes the [Acker] handle out of the message.
+    ///
+    /// It returns `None` in case the handle is already taken, or the
+    /// message is consumed without the acknowledgement.
+    ///
+    /// [Acker]: ../ack/struct.Acker.html
+    pub fn acker(&mut self) -> Option<crate::Acker> {
+        let acker = self.acker.take();
+        self.taken |= acker.is_some();
+        acker
+    }
+    /// Marks the message settled and returns true in case it's not yet.
+    ///
+    /// It returns false once the [Acker] handle is taken, as the message
+    /// is settled through the handle, and true for the message consumed
+    /// without the handle.
+    ///
+    /// [Acker]: ../ack/struct.Acker.html
+    pub(crate) fn settle(&self) -> bool {
+        match &self.acker {
+            Some(acker) => acker.settle(),
+            None => !self.taken,
+        }
+    }
+    /// Returns the `x-death` header entries, the most recent first.
+    pub fn deaths(&self) -> Vec<Death> {
+        match self.header_value("x-death") {
+            Some(lapin::types::AMQPValue::FieldArray(deaths)) => deaths
+                .as_slice()
+                .iter()
+                .filter_map(|death| match death {
+                    lapin::types::AMQPValue::FieldTable(death) => Some(Death::from_table(death)),
+                    _ => None,
+                })
+                .collect(),
+            _ => vec![],
+        }
+    }
+    #[inline]
+    pub(crate) fn properties(&self) -> &lapin::BasicProperties {
+        &self.delivery.properties
+    }
+    #[inline]
+    pub(crate) fn header_value(&self, key: &str) -> Option<&lapin::types::AMQPValue> {
+        header(&self.delivery.properties, key)
+    }
+}
+
+fn short_str(value: &Option<lapin::types::ShortString>) -> Option<&str> {
+    value.as_ref().map(|str| str.as_str())
+}
+
+/// A trait to convert the header value to the Rust type, used by the
+/// [Message::header] method.
+///
+/// The integer types are converted from any integer header value in range.
+///
+/// [Message::header]: struct.Message.html#method.header
+pub trait FromHeader: Sized {
+    fn from_header(value: &lapin::types::AMQPValue) -> Option<Self>;
+}
+
+impl FromHeader for String {
+    fn from_header(value: &lapin::types::AMQPValue) -> Option<Self> {
+        header_str(value).map(String::from)
+    }
+}
+
+impl FromHeader for bool {
+    fn from_header(value: &lapin::types::AMQPValue) -> Option<Self> {
+        match value {
+            lapin::types::AMQPValue::Boolean(v) => Some(*v),
+            _ => None,
+        }
+    }
+}
+
+impl FromHeader for f64 {
+    fn from_header(value: &lapin::types::AMQPValue) -> Option<Self> {
+        match value {
+            lapin::types::AMQPValue::Float(v) => Some(f64::from(*v)),
+            lapin::types::AMQPValue::Double(v) => Some(*v),
+            _ => header_i64(value).map(|v| v as f64),
+        }
+    }
+}
+
+impl FromHeader for Vec<u8> {
+    fn from_header(value: &lapin::types::AMQPValue) -> Option<Self> {
+        match value {
+            lapin::types::AMQPValue::ByteArray(v) => Some(v.as_slice().to_vec()),
+            _ => header_str(value).map(|v| v.as_bytes().to_vec()),
+        }
     }
 }
 
+impl FromHeader for Vec<String> {
+    fn from_header(value: &lapin::types::AMQPValue) -> Option<Self> {
+        match value {
+            lapin::types::AMQPValue::FieldArray(values) => values
+                .as_slice()
+                .iter()
+                .map(|v| header_str(v).map(String::from))
+                .collect(),
+            _ => None,
+        }
+    }
+}
+
+macro_rules! from_header_int {
+    ($($t:ty),*) => {
+        $(impl FromHeader for $t {
+            fn from_header(value: &lapin::types::AMQPValue) -> Option<Self> {
+                use std::convert::TryFrom;
+                match header_u64(value) {
+                    Some(v) => <$t>::try_from(v).ok(),
+                    None => header_i64(value).and_then(|v| <$t>::try_from(v).ok()),
+                }
+            }
+        })*
+    };
+}
+
+from_header_int!(u8, u16, u32, u64, i8, i16, i32, i64, usize);
+
+/// Returns the header value of the `key` in the provided properties.
+pub(crate) fn header<'a>(
+    props: &'a lapin::BasicProperties,
+    key: &str,
+) -> Option<&'a lapin::types::AMQPValue> {
+    props
+        .headers()
+        .as_ref()
+        .and_then(|headers| headers.inner().get(&lapin::types::ShortString::from(key)))
+}
+
+/// Returns the string slice of the short or long string header value.
+pub(crate) fn header_str(value: &lapin::types::AMQPValue) -> Option<&str> {
+    match value {
+        lapin::types::AMQPValue::ShortString(s) => Some(s.as_str()),
+        lapin::types::AMQPValue::LongString(s) => Some(s.as_str()),
+        _ => None,
+    }
+}
+
+/// Returns the unsigned integer of the integer header value.
+pub(crate) fn header_u64(value: &lapin::types::AMQPValue) -> Option<u64> {
+    use std::convert::TryFrom;
+    match value {
+        lapin::types::AMQPValue::ShortShortUInt(v) => Some(u64::from(*v)),
+        lapin::types::AMQPValue::ShortUInt(v) => Some(u64::from(*v)),
+        lapin::types::AMQPValue::LongUInt(v) => Some(u64::from(*v)),
+        lapin::types::AMQPValue::ShortShortInt(v) => u64::try_from(*v).ok(),
+        lapin::types::AMQPValue::ShortInt(v) => u64::try_from(*v).ok(),
+        lapin::types::AMQPValue::LongInt(v) => u64::try_from(*v).ok(),
+        lapin::types::AMQPValue::LongLongInt(v) => u64::try_from(*v).ok(),
+        lapin::types::AMQPValue::Timestamp(v) => Some(*v),
+        _ => None,
+    }
+}
+
+/// Returns the signed integer of the integer header value.
+pub(crate) fn header_i64(value: &lapin::types::AMQPValue) -> Option<i64> {
+    match value {
+        lapin::types::AMQPValue::ShortShortInt(v) => Some(i64::from(*v)),
+        lapin::types::AMQPValue::ShortInt(v) => Some(i64::from(*v)),
+        lapin::types::AMQPValue::LongInt(v) => Some(i64::from(*v)),
+        lapin::types::AMQPValue::LongLongInt(v) => Some(*v),
+        lapin::types::AMQPValue::ShortShortUInt(v) => Some(i64::from(*v)),
+        lapin::types::AMQPValue::ShortUInt(v) => Some(i64::from(*v)),
+        lapin::types::AMQPValue::LongUInt(v) => Some(i64::from(*v)),
+        _ => None,
+    }
+}
+
+/// Returns the new properties with the `key` header set to `value`.
+pub(crate) fn with_header(
+    props: lapin::BasicProperties,
+    key: &str,
+    value: lapin::types::AMQPValue,
+) -> lapin::BasicProperties {
+    let mut headers = props.headers().clone().unwrap_or_default();
+    headers.insert(key.into(), value);
+    props.with_headers(headers)
+}
+
+/// Returns the test message delivered with the `tag` through the `exchange`
+/// and the `routing_key`, carrying the properties and the data.
+#[cfg(test)]
+pub(crate) fn test_message(
+    tag: u64,
+    exchange: &str,
+    routing_key: &str,
+    props: lapin::BasicProperties,
+    data: &[u8],
+) -> Message {
+    let mut delivery =
+        lapin::message::Delivery::new(tag, exchange.into(), routing_key.into(), false);
+    delivery.properties = props;
+    delivery.data = data.to_vec();
+    Message::new(delivery)
+}
+
 /// A trait to peek the [Message] and returns success or error.
 ///
+/// The message failed to be peeked is settled with the error action but
+/// without the error reply, as it's not trusted yet.
+///
+/// The peeker is shared through [Arc] across the consumers and the
+/// concurrently peeked messages, so that its state, if any, needs the
+/// interior mutability.
+///
 /// [Message]: struct.Message.html
+/// [Arc]: https://doc.rust-lang.org/std/sync/struct.Arc.html
 #[async_trait]
 pub trait MessagePeek {
     /// Async method to peek a message.
-    async fn peek(&mut self, msg: &Message) -> Result<(), MessageError>;
-    fn boxed_clone(&self) -> Box<dyn MessagePeek + Send + Sync>;
-}
-
-// https://users.rust-lang.org/t/solved-is-it-possible-to-clone-a-boxed-trait-object/1714/6
-impl Clone for Box<dyn MessagePeek + Send + Sync> {
-    fn clone(&self) -> Box<dyn MessagePeek + Send + Sync> {
-        self.boxed_clone()
-    }
+    async fn peek(&self, msg: &Message) -> Result<(), MessageError>;
 }
 
 /// A trait to process the [Message] and returns the response data
 /// or modified data.
 ///
+/// The processor is shared through [Arc] across the consumers and the
+/// concurrently processed messages, e.g. with the database connection
+/// pool, so that its state, if any, needs the interior mutability.
+///
 /// [Message]: struct.Message.html
+/// [Arc]: https://doc.rust-lang.org/std/sync/struct.Arc.html
 #[async_trait]
 pub trait MessageProcess {
     /// Async method to process a message.
-    async fn process(&mut self, msg: &Message) -> Result<Vec<u8>, MessageError>;
-    fn boxed_clone(&self) -> Box<dyn MessageProcess + Send + Sync>;
-}
-
-// https://users.rust-lang.org/t/solved-is-it-possible-to-clone-a-boxed-trait-object/1714/6
-impl Clone for Box<dyn MessageProcess + Send + Sync> {
-    fn clone(&self) -> Box<dyn MessageProcess + Send + Sync> {
-        self.boxed_clone()
+    async fn process(&self, msg: &Message) -> Result<Vec<u8>, MessageError>;
+    /// Async method to process a message and returns the [Reply] with
+    /// the reply properties and headers.
+    ///
+    /// It returns the [process] method response as is by default, and
+    /// the [Consumer] calls this method instead of the [process] method.
+    ///
+    /// [Reply]: struct.Reply.html
+    /// [process]: #tymethod.process
+    /// [Consumer]: ../consume/struct.Consumer.html
+    async fn process_reply(&self, msg: &Message) -> Result<Reply, MessageError> {
+        self.process(msg).await.map(Reply::new)
     }
 }
 
@@ -89,12 +709,9 @@ pub struct NoopPeeker;
 #[async_trait]
 impl MessagePeek for NoopPeeker {
     /// Echoe back the request message.
-    async fn peek(&mut self, _msg: &Message) -> Result<(), MessageError> {
+    async fn peek(&self, _msg: &Message) -> Result<(), MessageError> {
         Ok(())
     }
-    fn boxed_clone(&self) -> Box<dyn MessagePeek + Send + Sync> {
-        Box::new((*self).clone())
-    }
 }
 
 /// A [MessagePeek] implementation which reject a message.
@@ -107,11 +724,8 @@ struct RejectPeeker;
 impl MessagePeek for RejectPeeker {
     /// Just returns the error saying to drop a message.
     /// to the console.  This is good for the benchmarking.
-    async fn peek(&mut self, _msg: &Message) -> Result<(), MessageError> {
-        Err(MessageError::Reject)
-    }
-    fn boxed_clone(&self) -> Box<dyn MessagePeek + Send + Sync> {
-        Box::new((*self).clone())
+    async fn peek(&self, _msg: &Message) -> Result<(), MessageError> {
+        Err(MessageError::reject())
     }
 }
 
@@ -124,10 +738,162 @@ pub struct EchoProcessor;
 #[async_trait]
 impl MessageProcess for EchoProcessor {
     /// Echoe back the request message.
-    async fn process(&mut self, msg: &Message) -> Result<Vec<u8>, MessageError> {
+    async fn process(&self, msg: &Message) -> Result<Vec<u8>, MessageError> {
         Ok(msg.data().to_vec())
     }
-    fn boxed_clone(&self) -> Box<dyn MessageProcess + Send + Sync> {
-        Box::new((*self).clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MessageError;
+    use lapin::types::{AMQPValue, FieldArray, FieldTable};
+    #[test]
+    fn header() {
+        let mut headers = FieldTable::default();
+        headers.insert("string".into(), AMQPValue::LongString("value".into()));
+        headers.insert("short".into(), AMQPValue::ShortString("value".into()));
+        headers.insert("bool".into(), AMQPValue::Boolean(true));
+        headers.insert("u32".into(), AMQPValue::LongUInt(300));
+        headers.insert("negative".into(), AMQPValue::LongLongInt(-1));
+        headers.insert("double".into(), AMQPValue::Double(1.5));
+        let keys = vec![AMQPValue::LongString("a".into())];
+        headers.insert(
+            "array".into(),
+            AMQPValue::FieldArray(FieldArray::from(keys)),
+        );
+        let mut delivery = lapin::message::Delivery::new(1, "ex".into(), "key".into(), true);
+        delivery.properties = lapin::BasicProperties::default()
+            .with_headers(headers)
+            .with_expiration("1500".into())
+            .with_timestamp(10);
+        let msg = super::Message::new(delivery);
+        assert_eq!("ex", msg.exchange());
+        assert_eq!("key", msg.routing_key());
+        assert!(msg.redelivered());
+        assert_eq!(None, msg.correlation_id());
+        assert_eq!(
+            Some(std::time::Duration::from_millis(1500)),
+            msg.expiration()
+        );
+        assert_eq!(
+            Some(std::time::UNIX_EPOCH + std::time::Duration::from_secs(10)),
+            msg.timestamp()
+        );
+        assert_eq!(Some(String::from("value")), msg.header::<String>("string"));
+        assert_eq!(Some(String::from("value")), msg.header::<String>("short"));
+        assert_eq!(None, msg.header::<String>("bool"));
+        assert_eq!(Some(true), msg.header::<bool>("bool"));
+        assert_eq!(Some(300), msg.header::<u32>("u32"));
+        assert_eq!(Some(300), msg.header::<i64>("u32"));
+        assert_eq!(None, msg.header::<u8>("u32"));
+        assert_eq!(Some(-1), msg.header::<i32>("negative"));
+        assert_eq!(None, msg.header::<u64>("negative"));
+        assert_eq!(Some(1.5), msg.header::<f64>("double"));
+        assert_eq!(
+            Some(vec![String::from("a")]),
+            msg.header::<Vec<String>>("array")
+        );
+        assert_eq!(None, msg.header::<String>("missing"));
+        assert_eq!(7, msg.header_names().len());
+    }
+    #[test]
+    fn reply_properties() {
+        let props = lapin::BasicProperties::default()
+            .with_correlation_id("id".into())
+            .with_content_type("text/plain".into());
+        let reply = super::Reply::new(b"data".to_vec())
+            .with_content_type("application/json")
+            .with_header("x-status", "ok");
+        let got = reply.properties(props);
+        assert_eq!(&Some("id".into()), got.correlation_id());
+        assert_eq!(&Some("application/json".into()), got.content_type());
+        assert_eq!(&None, got.content_encoding());
+        assert_eq!(
+            Some(&AMQPValue::LongString("ok".into())),
+            super::header(&got, "x-status")
+        );
+    }
+    #[test]
+    fn message_error() {
+        struct Test {
+            name: &'static str,
+            err: MessageError,
+            want: MessageError,
+            want_reply: Option<Vec<u8>>,
+            want_requeue: bool,
+        }
+        let tests = [
+            Test {
+                name: "drop ignores the reason",
+                err: MessageError::Drop.with_reason("bad").with_requeue(true),
+                want: MessageError::Drop,
+                want_reply: None,
+                want_requeue: false,
+            },
+            Test {
+                name: "reject with requeue",
+                err: MessageError::reject().with_requeue(true),
+                want: MessageError::Reject {
+                    requeue: true,
+                    status: None,
+                    reason: None,
+                    reply: None,
+                },
+                want_reply: Some(vec![]),
+                want_requeue: true,
+            },
+            Test {
+                name: "reject with reason",
+                err: MessageError::reject().with_reason("bad request"),
+                want: MessageError::Reject {
+                    requeue: false,
+                    status: None,
+                    reason: Some(String::from("bad request")),
+                    reply: None,
+                },
+                want_reply: Some(vec![]),
+                want_requeue: false,
+            },
+            Test {
+                name: "nack with multiple and reply",
+                err: MessageError::nack()
+                    .with_multiple(true)
+                    .with_reply(b"error".to_vec()),
+                want: MessageError::Nack {
+                    requeue: false,
+                    multiple: true,
+                    status: None,
+                    reason: None,
+                    reply: Some(b"error".to_vec()),
+                },
+                want_reply: Some(b"error".to_vec()),
+                want_requeue: false,
+            },
+            Test {
+                name: "status only",
+                err: MessageError::nack().with_status(crate::rpc::Status::NOT_FOUND),
+                want: MessageError::Nack {
+                    requeue: false,
+                    multiple: false,
+                    status: Some(crate::rpc::Status::NOT_FOUND),
+                    reason: None,
+                    reply: None,
+                },
+                want_reply: Some(vec![]),
+                want_requeue: false,
+            },
+            Test {
+                name: "reject ignores multiple",
+                err: MessageError::reject().with_multiple(true),
+                want: MessageError::reject(),
+                want_reply: Some(vec![]),
+                want_requeue: false,
+            },
+        ];
+        for t in &tests {
+            assert_eq!(t.want, t.err, "{}", t.name);
+            assert_eq!(t.want_reply, t.err.reply(), "{}", t.name);
+            assert_eq!(t.want_requeue, t.err.requeue(), "{}", t.name);
+        }
     }
 }
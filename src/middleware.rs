@@ -0,0 +1,405 @@
+// SPDX-License-Identifier: Apache-2.0 AND MIT
+//! `Middleware` trait and `Stack` struct, with the built-in middlewares
+use async_trait::async_trait;
+use futures::future::FutureExt;
+use std::any::Any;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The result of the wrapped [MessageProcess] or [MessagePeek], where
+/// the peeked message has no [Reply].
+///
+/// [MessageProcess]: ../message/trait.MessageProcess.html
+/// [MessagePeek]: ../message/trait.MessagePeek.html
+/// [Reply]: ../message/struct.Reply.html
+pub type Outcome = Result<Option<crate::Reply>, crate::MessageError>;
+
+/// A cross-cutting logic around the [MessageProcess] and [MessagePeek]
+/// trait objects.
+///
+/// It's layered through the [ConsumerBuilder::layer] and
+/// [ProducerBuilder::layer] methods, around the consumer's
+/// [MessageProcess] and the producer's reply [MessagePeek] respectively,
+/// so that the hooks are called once for each message.  The first layered
+/// middleware is the outermost one, e.g. its `before` hook is called first
+/// and its `after` hook is called last.
+///
+/// [MessageProcess]: ../message/trait.MessageProcess.html
+/// [MessagePeek]: ../message/trait.MessagePeek.html
+/// [ConsumerBuilder::layer]: ../consume/struct.ConsumerBuilder.html#method.layer
+/// [ProducerBuilder::layer]: ../produce/struct.ProducerBuilder.html#method.layer
+#[async_trait]
+pub trait Middleware {
+    /// Async method called before the message is processed, or peeked.
+    ///
+    /// The error short-circuits the inner middlewares and the message
+    /// processing.
+    async fn before(&self, _msg: &crate::Message) -> Result<(), crate::MessageError> {
+        Ok(())
+    }
+    /// Async method called after the message is processed, or peeked,
+    /// with the elapsed time and the modifiable result.
+    ///
+    /// It's called only in case the `before` hook of the middleware
+    /// succeeded.
+    async fn after(&self, _msg: &crate::Message, _elapsed: Duration, _outcome: &mut Outcome) {}
+    /// Returns the error action of the panicked message processing, or
+    /// `None` to leave it to the other middlewares.  The panic is resumed
+    /// in case none of the middlewares handles it.
+    fn on_panic(
+        &self,
+        _msg: &crate::Message,
+        _panic: &(dyn Any + Send),
+    ) -> Option<crate::MessageError> {
+        None
+    }
+}
+
+/// A [MessageProcess] and [MessagePeek] implementation wrapping the
+/// inner trait object with the [Middleware] layers.
+///
+/// [MessageProcess]: ../message/trait.MessageProcess.html
+/// [MessagePeek]: ../message/trait.MessagePeek.html
+/// [Middleware]: trait.Middleware.html
+#[derive(Clone)]
+pub struct Stack<T> {
+    inner: T,
+    layers: Vec<Arc<dyn Middleware + Send + Sync>>,
+}
+
+impl<T> Stack<T> {
+    pub fn new(inner: T, layers: Vec<Arc<dyn Middleware + Send + Sync>>) -> Self {
+        Self { inner, layers }
+    }
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+/// Returns the peeker wrapped with the middleware `layers`, if any.
+pub(crate) fn peeker(
+    peeker: Arc<dyn crate::MessagePeek + Send + Sync>,
+    layers: &[Arc<dyn Middleware + Send + Sync>],
+) -> Arc<dyn crate::MessagePeek + Send + Sync> {
+    if layers.is_empty() {
+        return peeker;
+    }
+    Arc::new(Stack::new(peeker, layers.to_vec()))
+}
+
+/// Returns the processor wrapped with the middleware `layers`, if any.
+pub(crate) fn processor(
+    processor: Arc<dyn crate::MessageProcess + Send + Sync>,
+    layers: &[Arc<dyn Middleware + Send + Sync>],
+) -> Arc<dyn crate::MessageProcess + Send + Sync> {
+    if layers.is_empty() {
+        return processor;
+    }
+    Arc::new(Stack::new(processor, layers.to_vec()))
+}
+
+/// Runs the `before` hooks, the `f` future, catching its panic, and then
+/// the `after` hooks of the middlewares whose `before` hooks succeeded, in
+/// reverse.
+async fn call<F>(
+    layers: &[Arc<dyn Middleware + Send + Sync>],
+    msg: &crate::Message,
+    f: F,
+) -> Outcome
+where
+    F: std::future::Future<Output = Outcome>,
+{
+    let start = Instant::now();
+    let mut called = 0;
+    let mut outcome = Ok(None);
+    for layer in layers {
+        if let Err(err) = layer.before(msg).await {
+            outcome = Err(err);
+            break;
+        }
+        called += 1;
+    }
+    if outcome.is_ok() {
+        outcome = match AssertUnwindSafe(f).catch_unwind().await {
+            Ok(outcome) => outcome,
+            Err(panic) => match layers
+                .iter()
+                .find_map(|layer| layer.on_panic(msg, panic.as_ref()))
+            {
+                Some(err) => Err(err),
+                None => std::panic::resume_unwind(panic),
+            },
+        };
+    }
+    let elapsed = start.elapsed();
+    for layer in layers[..called].iter().rev() {
+        layer.after(msg, elapsed, &mut outcome).await;
+    }
+    outcome
+}
+
+#[async_trait]
+impl crate::MessageProcess for Stack<Arc<dyn crate::MessageProcess + Send + Sync>> {
+    async fn process(&self, msg: &crate::Message) -> Result<Vec<u8>, crate::MessageError> {
+        self.process_reply(msg)
+            .await
+            .map(|reply| reply.data().to_vec())
+    }
+    async fn process_reply(
+        &self,
+        msg: &crate::Message,
+    ) -> Result<crate::Reply, crate::MessageError> {
+        let f = self.inner.process_reply(msg);
+        call(&self.layers, msg, async { f.await.map(Some) })
+            .await
+            .map(|reply| reply.unwrap_or_default())
+    }
+}
+
+#[async_trait]
+impl crate::MessagePeek for Stack<Arc<dyn crate::MessagePeek + Send + Sync>> {
+    async fn peek(&self, msg: &crate::Message) -> Result<(), crate::MessageError> {
+        let f = self.inner.peek(msg);
+        call(&self.layers, msg, async { f.await.map(|()| None) })
+            .await
+            .map(|_| ())
+    }
+}
+
+/// A [Middleware] logging the message outcome through the [log] crate.
+///
+/// [Middleware]: trait.Middleware.html
+/// [log]: https://docs.rs/log
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Logger;
+
+#[async_trait]
+impl Middleware for Logger {
+    async fn after(&self, msg: &crate::Message, elapsed: Duration, outcome: &mut Outcome) {
+        match outcome {
+            Ok(_) => log::info!(
+                "message {} from '{}' with '{}' processed in {:?}",
+                msg.delivery_tag(),
+                msg.exchange(),
+                msg.routing_key(),
+                elapsed,
+            ),
+            Err(err) => log::warn!(
+                "message {} from '{}' with '{}' failed in {:?}: {:?}",
+                msg.delivery_tag(),
+                msg.exchange(),
+                msg.routing_key(),
+                elapsed,
+                err,
+            ),
+        }
+    }
+}
+
+type TimerFn = Arc<dyn Fn(&crate::Message, Duration) + Send + Sync>;
+
+/// A [Middleware] reporting the message processing time to the callback.
+///
+/// [Middleware]: trait.Middleware.html
+#[derive(Clone)]
+pub struct Timer {
+    f: TimerFn,
+}
+
+impl Timer {
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(&crate::Message, Duration) + Send + Sync + 'static,
+    {
+        Self { f: Arc::new(f) }
+    }
+}
+
+#[async_trait]
+impl Middleware for Timer {
+    async fn after(&self, msg: &crate::Message, elapsed: Duration, _: &mut Outcome) {
+        (self.f)(msg, elapsed)
+    }
+}
+
+/// A [Middleware] converting the panic to the [MessageError] action,
+/// with the panic message as the reason.
+///
+/// It nacks the message without requeue by default.
+///
+/// [Middleware]: trait.Middleware.html
+/// [MessageError]: ../message/enum.MessageError.html
+#[derive(Clone, Debug)]
+pub struct CatchPanic {
+    err: crate::MessageError,
+}
+
+impl Default for CatchPanic {
+    fn default() -> Self {
+        Self {
+            err: crate::MessageError::nack(),
+        }
+    }
+}
+
+impl CatchPanic {
+    pub fn new(err: crate::MessageError) -> Self {
+        Self { err }
+    }
+}
+
+#[async_trait]
+impl Middleware for CatchPanic {
+    fn on_panic(
+        &self,
+        _: &crate::Message,
+        panic: &(dyn Any + Send),
+    ) -> Option<crate::MessageError> {
+        let reason = match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+            (Some(reason), _) => reason,
+            (_, Some(reason)) => reason.as_str(),
+            (None, None) => "unknown",
+        };
+        Some(
+            self.err
+                .clone()
+                .with_reason(&format!("panicked: {}", reason)),
+        )
+    }
+}
+
+/// A [Middleware] injecting the header to the replies.
+///
+/// [Middleware]: trait.Middleware.html
+#[derive(Clone, Debug)]
+pub struct InjectHeader {
+    key: String,
+    value: String,
+}
+
+impl InjectHeader {
+    pub fn new(key: &str, value: &str) -> Self {
+        Self {
+            key: key.to_string(),
+            value: value.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware for InjectHeader {
+    async fn after(&self, _: &crate::Message, _: Duration, outcome: &mut Outcome) {
+        if let Ok(Some(reply)) = outcome {
+            *reply = std::mem::take(reply).with_header(&self.key, &self.value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MessageError;
+    use std::sync::Mutex;
+    /// A middleware recording the hook calls.
+    struct Record {
+        name: &'static str,
+        calls: Arc<Mutex<Vec<String>>>,
+        fail: bool,
+    }
+    #[async_trait]
+    impl Middleware for Record {
+        async fn before(&self, _: &crate::Message) -> Result<(), MessageError> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("{} before", self.name));
+            if self.fail {
+                return Err(MessageError::reject().with_reason(self.name));
+            }
+            Ok(())
+        }
+        async fn after(&self, _: &crate::Message, _: Duration, _: &mut Outcome) {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("{} after", self.name));
+        }
+    }
+    #[derive(Clone)]
+    struct Processor;
+    #[async_trait]
+    impl crate::MessageProcess for Processor {
+        async fn process(&self, msg: &crate::Message) -> Result<Vec<u8>, MessageError> {
+            match msg.data() {
+                b"panic" => panic!("boom"),
+                data => Ok(data.to_vec()),
+            }
+        }
+    }
+    #[test]
+    fn process_reply() {
+        struct Test {
+            name: &'static str,
+            fail: Option<&'static str>,
+            data: &'static [u8],
+            want: Result<crate::Reply, MessageError>,
+            calls: Vec<&'static str>,
+        }
+        let tests = [
+            Test {
+                name: "all layers",
+                fail: None,
+                data: b"data",
+                want: Ok(crate::Reply::new(b"data".to_vec()).with_header("x-layer", "inject")),
+                calls: vec!["outer before", "inner before", "inner after", "outer after"],
+            },
+            Test {
+                name: "inner before failure",
+                fail: Some("inner"),
+                data: b"data",
+                want: Err(MessageError::reject().with_reason("inner")),
+                calls: vec!["outer before", "inner before", "outer after"],
+            },
+            Test {
+                name: "outer before failure",
+                fail: Some("outer"),
+                data: b"data",
+                want: Err(MessageError::reject().with_reason("outer")),
+                calls: vec!["outer before"],
+            },
+            Test {
+                name: "panic",
+                fail: None,
+                data: b"panic",
+                want: Err(MessageError::nack().with_reason("panicked: boom")),
+                calls: vec!["outer before", "inner before", "inner after", "outer after"],
+            },
+        ];
+        for t in &tests {
+            let calls = Arc::new(Mutex::new(vec![]));
+            let layers: Vec<Arc<dyn Middleware + Send + Sync>> = vec![
+                Arc::new(CatchPanic::default()),
+                Arc::new(InjectHeader::new("x-layer", "inject")),
+                Arc::new(Record {
+                    name: "outer",
+                    calls: calls.clone(),
+                    fail: t.fail == Some("outer"),
+                }),
+                Arc::new(Record {
+                    name: "inner",
+                    calls: calls.clone(),
+                    fail: t.fail == Some("inner"),
+                }),
+            ];
+            let inner: Arc<dyn crate::MessageProcess + Send + Sync> = Arc::new(Processor);
+            let stack = Stack::new(inner, layers);
+            let props = lapin::BasicProperties::default();
+            let msg = crate::message::test_message(1, "", "", props, t.data);
+            let got =
+                futures::executor::block_on(crate::MessageProcess::process_reply(&stack, &msg));
+            assert_eq!(t.want, got, "{}", t.name);
+            assert_eq!(t.calls, *calls.lock().unwrap(), "{}", t.name);
+        }
+    }
+}
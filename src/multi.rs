@@ -0,0 +1,185 @@
+// SPDX-License-Identifier: Apache-2.0 AND MIT
+//! `MultiConsumer` struct to consume multiple queues over a channel
+use futures::stream::Stream;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// A [Stream] of the messages from multiple queues over a single channel,
+/// tagged with the queue name.
+///
+/// The ready queues are selected by the smooth weighted round-robin, so
+/// that the queue of the weight 3 yields three messages for each message
+/// of the queue of the weight 1, while any of those has messages.  The
+/// messages are acknowledged through the [Acker] over the channel, unless
+/// those are consumed with the `no_ack` option.
+///
+/// It's built by the [ConsumerBuilder::build_multi] method.
+///
+/// [Stream]: https://docs.rs/futures/latest/futures/stream/trait.Stream.html
+/// [Acker]: ../ack/struct.Acker.html
+/// [ConsumerBuilder::build_multi]: ../consume/struct.ConsumerBuilder.html#method.build_multi
+pub struct MultiConsumer {
+    ch: lapin::Channel,
+    consumes: Vec<(String, lapin::Consumer)>,
+    scheduler: Scheduler,
+    ack: bool,
+}
+
+impl MultiConsumer {
+    pub(crate) fn new(
+        ch: lapin::Channel,
+        consumes: Vec<(String, lapin::Consumer, u32)>,
+        ack: bool,
+    ) -> Self {
+        let weights = consumes.iter().map(|(_, _, weight)| *weight).collect();
+        let consumes = consumes
+            .into_iter()
+            .map(|(queue, consume, _)| (queue, consume))
+            .collect();
+        Self {
+            ch,
+            consumes,
+            scheduler: Scheduler::new(weights),
+            ack,
+        }
+    }
+    /// Returns the names of the queues still consumed.
+    pub fn queues(&self) -> Vec<&str> {
+        self.consumes
+            .iter()
+            .map(|(queue, _)| queue.as_str())
+            .collect()
+    }
+}
+
+impl Stream for MultiConsumer {
+    type Item = Result<(String, crate::Message), crate::Error>;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut ended = vec![];
+        let mut ready = None;
+        for i in self.scheduler.order() {
+            match Pin::new(&mut self.consumes[i].1).poll_next(cx) {
+                Poll::Ready(Some(msg)) => {
+                    ready = Some((i, msg));
+                    break;
+                }
+                Poll::Ready(None) => ended.push(i),
+                Poll::Pending => {}
+            }
+        }
+        let ret = match ready {
+            Some((i, Ok(msg))) => {
+                self.scheduler.pick(i);
+                let queue = self.consumes[i].0.clone();
+                let msg = if self.ack {
+                    crate::Message::with_acker(msg, self.ch.clone())
+                } else {
+                    crate::Message::new(msg)
+                };
+                Poll::Ready(Some(Ok((queue, msg))))
+            }
+            Some((_, Err(err))) => Poll::Ready(Some(Err(err.into()))),
+            None => Poll::Pending,
+        };
+        ended.sort_unstable();
+        for i in ended.into_iter().rev() {
+            self.consumes.remove(i);
+            self.scheduler.remove(i);
+        }
+        match ret {
+            Poll::Pending if self.consumes.is_empty() => Poll::Ready(None),
+            ret => ret,
+        }
+    }
+}
+
+/// A smooth weighted round-robin scheduler.
+struct Scheduler {
+    weights: Vec<i64>,
+    current: Vec<i64>,
+}
+
+impl Scheduler {
+    fn new(weights: Vec<u32>) -> Self {
+        let weights: Vec<_> = weights.into_iter().map(|w| i64::from(w.max(1))).collect();
+        let current = vec![0; weights.len()];
+        Self { weights, current }
+    }
+    /// Returns the indices in the selection order, preferring the lower
+    /// index on tie.
+    fn order(&self) -> Vec<usize> {
+        let mut order: Vec<_> = (0..self.weights.len()).collect();
+        order.sort_by_key(|&i| std::cmp::Reverse(self.current[i] + self.weights[i]));
+        order
+    }
+    /// Records the `i`th selection.
+    ///
+    /// The current weights are capped by the total weight not to burst the
+    /// queue once it's ready again after a while.
+    fn pick(&mut self, i: usize) {
+        let total: i64 = self.weights.iter().sum();
+        for (current, weight) in self.current.iter_mut().zip(&self.weights) {
+            *current = (*current + weight).min(total);
+        }
+        self.current[i] -= total;
+    }
+    fn remove(&mut self, i: usize) {
+        self.weights.remove(i);
+        self.current.remove(i);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn scheduler() {
+        struct Test {
+            name: &'static str,
+            weights: Vec<u32>,
+            ready: Vec<bool>,
+            want: Vec<usize>,
+        }
+        let tests = [
+            Test {
+                name: "equal weights",
+                weights: vec![1, 1],
+                ready: vec![true, true],
+                want: vec![0, 1, 0, 1],
+            },
+            Test {
+                name: "three to one",
+                weights: vec![3, 1],
+                ready: vec![true, true],
+                want: vec![0, 0, 1, 0, 0, 0, 1, 0],
+            },
+            Test {
+                name: "zero weight as one",
+                weights: vec![0, 1],
+                ready: vec![true, true],
+                want: vec![0, 1, 0, 1],
+            },
+            Test {
+                name: "heavier queue not ready",
+                weights: vec![3, 1],
+                ready: vec![false, true],
+                want: vec![1, 1, 1, 1],
+            },
+            Test {
+                name: "smooth five to one to one",
+                weights: vec![5, 1, 1],
+                ready: vec![true, true, true],
+                want: vec![0, 0, 1, 0, 2, 0, 0],
+            },
+        ];
+        for t in &tests {
+            let mut scheduler = super::Scheduler::new(t.weights.clone());
+            let mut got = vec![];
+            for _ in 0..t.want.len() {
+                let i = scheduler.order().into_iter().find(|&i| t.ready[i]).unwrap();
+                scheduler.pick(i);
+                got.push(i);
+            }
+            assert_eq!(t.want, got, "{}", t.name);
+        }
+    }
+}
@@ -1,6 +1,7 @@
 // SPDX-License-Identifier: Apache-2.0 AND MIT
 //! `ProducerBuilder` and `Producer` structs
 use futures_util::stream::StreamExt;
+use std::sync::Arc;
 
 /// A [non-consuming] [Producer] builder.
 ///
@@ -21,8 +22,12 @@ pub struct ProducerBuilder {
     rx_opts: lapin::options::BasicConsumeOptions,
     ack_opts: lapin::options::BasicAckOptions,
     rej_opts: lapin::options::BasicRejectOptions,
-    nack_opts: lapin::options::BasicNackOptions,
-    peeker: Box<dyn crate::MessagePeek + Send + Sync>,
+    peeker: Arc<dyn crate::MessagePeek + Send + Sync>,
+    layers: Vec<Arc<dyn crate::middleware::Middleware + Send + Sync>>,
+    signer: Option<crate::sign::Signer>,
+    #[cfg(feature = "encryption")]
+    encryption: Option<crate::crypto::Encryption>,
+    chunk_size: Option<usize>,
 }
 
 impl ProducerBuilder {
@@ -41,8 +46,12 @@ impl ProducerBuilder {
             rx_opts: lapin::options::BasicConsumeOptions::default(),
             ack_opts: lapin::options::BasicAckOptions::default(),
             rej_opts: lapin::options::BasicRejectOptions::default(),
-            nack_opts: lapin::options::BasicNackOptions::default(),
-            peeker: Box::new(crate::message::NoopPeeker {}),
+            peeker: Arc::new(crate::message::NoopPeeker {}),
+            layers: Vec::new(),
+            signer: None,
+            #[cfg(feature = "encryption")]
+            encryption: None,
+            chunk_size: None,
         }
     }
     /// Specify the exchange name.
@@ -58,10 +67,62 @@ impl ProducerBuilder {
     /// Use the provided [MessagePeek] trait object.
     ///
     /// [MessagePeek]: ../message/trait.MessagePeek.html
-    pub fn with_peeker(&mut self, peeker: Box<dyn crate::MessagePeek + Send + Sync>) -> &mut Self {
+    pub fn with_peeker(&mut self, peeker: Arc<dyn crate::MessagePeek + Send + Sync>) -> &mut Self {
         self.peeker = peeker;
         self
     }
+    /// Wrap the reply [MessagePeek] with the provided [Middleware].
+    ///
+    /// The first layered middleware is the outermost one.
+    ///
+    /// [MessagePeek]: ../message/trait.MessagePeek.html
+    /// [Middleware]: ../middleware/trait.Middleware.html
+    pub fn layer<M>(&mut self, middleware: M) -> &mut Self
+    where
+        M: crate::middleware::Middleware + Send + Sync + 'static,
+    {
+        self.layers.push(Arc::new(middleware));
+        self
+    }
+    /// Sign the messages with the provided [Signer].
+    ///
+    /// [Signer]: ../sign/struct.Signer.html
+    pub fn with_signer(&mut self, signer: crate::sign::Signer) -> &mut Self {
+        self.signer = Some(signer);
+        self
+    }
+    /// Encrypt the messages and decrypt the replies with the provided
+    /// [Encryption].
+    ///
+    /// [Encryption]: ../crypto/struct.Encryption.html
+    #[cfg(feature = "encryption")]
+    pub fn with_encryption(&mut self, encryption: crate::crypto::Encryption) -> &mut Self {
+        self.encryption = Some(encryption);
+        self
+    }
+    /// Specify the schema version of the message payload, carried by
+    /// the [SCHEMA_VERSION_HEADER] header.
+    ///
+    /// [SCHEMA_VERSION_HEADER]: ../schema/constant.SCHEMA_VERSION_HEADER.html
+    pub fn schema_version(&mut self, version: u32) -> &mut Self {
+        self.tx_props = crate::message::with_header(
+            self.tx_props.clone(),
+            crate::schema::SCHEMA_VERSION_HEADER,
+            lapin::types::AMQPValue::LongUInt(version),
+        );
+        self
+    }
+    /// Split the published messages larger than `size` bytes into chunks.
+    ///
+    /// The consumer reassembles the chunks through [Reassembler].
+    /// It panics when the `size` is zero.
+    ///
+    /// [Reassembler]: ../chunk/struct.Reassembler.html
+    pub fn chunk_size(&mut self, size: usize) -> &mut Self {
+        assert!(size > 0, "zero chunk size");
+        self.chunk_size = Some(size);
+        self
+    }
     pub async fn build(&self) -> crate::Result<Producer> {
         let tx = self.conn.channel().await?;
         let queue_opts = lapin::options::QueueDeclareOptions {
@@ -102,8 +163,13 @@ impl ProducerBuilder {
             tx_opts: self.tx_opts.clone(),
             ack_opts: self.ack_opts.clone(),
             rej_opts: self.rej_opts.clone(),
-            nack_opts: self.nack_opts.clone(),
-            peeker: self.peeker.clone(),
+            peeker: crate::middleware::peeker(self.peeker.clone(), &self.layers),
+            layers: self.layers.clone(),
+            signer: self.signer.clone(),
+            #[cfg(feature = "encryption")]
+            encryption: self.encryption.clone(),
+            chunk_size: self.chunk_size,
+            correlation_id: 0,
         })
     }
 }
@@ -122,74 +188,187 @@ pub struct Producer {
     tx_opts: lapin::options::BasicPublishOptions,
     ack_opts: lapin::options::BasicAckOptions,
     rej_opts: lapin::options::BasicRejectOptions,
-    nack_opts: lapin::options::BasicNackOptions,
-    peeker: Box<dyn crate::MessagePeek + Send>,
+    peeker: Arc<dyn crate::MessagePeek + Send + Sync>,
+    layers: Vec<Arc<dyn crate::middleware::Middleware + Send + Sync>>,
+    signer: Option<crate::sign::Signer>,
+    #[cfg(feature = "encryption")]
+    encryption: Option<crate::crypto::Encryption>,
+    chunk_size: Option<usize>,
+    correlation_id: u64,
 }
 
 impl Producer {
-    /// Use the provided [MessagePeek] trait object.
+    /// Use the provided [MessagePeek] trait object, wrapped with the
+    /// builder's [Middleware] layers.
     ///
     /// [MessagePeek]: ../message/trait.MessagePeek.html
-    pub fn with_peeker(&mut self, peeker: Box<dyn crate::MessagePeek + Send + Sync>) -> &mut Self {
-        self.peeker = peeker;
+    /// [Middleware]: ../middleware/trait.Middleware.html
+    pub fn with_peeker(&mut self, peeker: Arc<dyn crate::MessagePeek + Send + Sync>) -> &mut Self {
+        self.peeker = crate::middleware::peeker(peeker, &self.layers);
+        self
+    }
+    /// Sign the messages with the provided [Signer].
+    ///
+    /// It's useful to rotate the key of the running producer.
+    ///
+    /// [Signer]: ../sign/struct.Signer.html
+    pub fn with_signer(&mut self, signer: crate::sign::Signer) -> &mut Self {
+        self.signer = Some(signer);
         self
     }
     pub async fn publish(&mut self, msg: Vec<u8>) -> crate::Result<()> {
-        self.tx
-            .basic_publish(
-                &self.ex,
-                &self.queue,
-                self.tx_opts.clone(),
-                msg,
-                self.tx_props.clone(),
-            )
-            .await
-            .map_err(crate::Error::from)?;
-        Ok(())
+        self.publish_with(self.tx_props.clone(), msg).await
     }
+    /// Send the RPC request and returns the reply.
+    ///
+    /// Each request carries the unique correlation ID, so that the replies
+    /// of the other requests, e.g. the ones cancelled by the timeout, are
+    /// dropped.
     pub async fn rpc(&mut self, msg: Vec<u8>) -> crate::Result<Vec<u8>> {
-        self.tx
-            .basic_publish(
-                &self.ex,
-                &self.queue,
-                self.tx_opts.clone(),
-                msg,
-                self.rx_props.clone(),
-            )
-            .await
-            .map_err(crate::Error::from)?;
-        if let Some(msg) = self.consume.next().await {
-            match msg {
-                Ok(msg) => return self.recv(&crate::Message::new(msg)).await,
-                Err(err) => return Err(crate::Error::from(err)),
+        self.rpc_with(self.rx_props.clone(), msg).await
+    }
+    #[inline]
+    pub(crate) fn tx_props(&self) -> &lapin::BasicProperties {
+        &self.tx_props
+    }
+    #[inline]
+    pub(crate) fn rx_props(&self) -> &lapin::BasicProperties {
+        &self.rx_props
+    }
+    /// Publish the message with the provided properties, e.g. the
+    /// producer's properties with the content type set.
+    pub(crate) async fn publish_with(
+        &mut self,
+        props: lapin::BasicProperties,
+        msg: Vec<u8>,
+    ) -> crate::Result<()> {
+        let (props, msg) = self.encrypt(props, msg)?;
+        match self.chunk_size {
+            Some(size) => {
+                for (props, chunk) in crate::chunk::split(props, msg, size) {
+                    self.send(chunk, props).await?;
+                }
+                Ok(())
+            }
+            None => self.send(msg, props).await,
+        }
+    }
+    /// Send the RPC request with the provided properties, which should
+    /// carry the `reply_to` queue, and returns the reply.
+    pub(crate) async fn rpc_with(
+        &mut self,
+        props: lapin::BasicProperties,
+        msg: Vec<u8>,
+    ) -> crate::Result<Vec<u8>> {
+        self.correlation_id += 1;
+        let id = self.correlation_id.to_string();
+        let props = props.with_correlation_id(id.as_str().into());
+        let (props, msg) = self.encrypt(props, msg)?;
+        self.send(msg, props).await?;
+        while let Some(msg) = self.consume.next().await {
+            let mut msg = crate::Message::new(msg.map_err(crate::Error::from)?);
+            if msg.correlation_id() != Some(id.as_str()) {
+                // Drop the stale reply of the cancelled request.
+                self.rx
+                    .basic_ack(msg.delivery_tag(), self.ack_opts.clone())
+                    .await
+                    .map_err(crate::Error::from)?;
+                continue;
             }
+            return self.recv(&mut msg).await;
         }
         Ok(vec![])
     }
-    async fn recv(&mut self, msg: &crate::Message) -> crate::Result<Vec<u8>> {
+    async fn send(&mut self, msg: Vec<u8>, props: lapin::BasicProperties) -> crate::Result<()> {
+        let props = match &self.signer {
+            Some(signer) => signer.sign(&self.queue, props, &msg),
+            None => props,
+        };
+        self.tx
+            .basic_publish(&self.ex, &self.queue, self.tx_opts.clone(), msg, props)
+            .await
+            .map_err(crate::Error::from)?;
+        Ok(())
+    }
+    async fn recv(&mut self, msg: &mut crate::Message) -> crate::Result<Vec<u8>> {
         match self.peeker.peek(msg).await {
             Ok(()) => {
+                if !self.decrypt(msg) {
+                    self.rx
+                        .basic_reject(msg.delivery_tag(), self.rej_opts.clone())
+                        .await
+                        .map_err(crate::Error::from)?;
+                    return Err(crate::Error::Crypto(String::from("decryption failure")));
+                }
                 self.rx
                     .basic_ack(msg.delivery_tag(), self.ack_opts.clone())
                     .await
                     .map_err(crate::Error::from)?;
+                crate::rpc::check(msg).map_err(crate::Error::Remote)?;
                 Ok(msg.data().to_vec())
             }
-            Err(crate::MessageError::Drop) => Ok(vec![]),
-            Err(crate::MessageError::Reject) => {
+            Err(crate::MessageError::Drop) => {
                 self.rx
-                    .basic_reject(msg.delivery_tag(), self.rej_opts.clone())
+                    .basic_ack(msg.delivery_tag(), self.ack_opts.clone())
                     .await
                     .map_err(crate::Error::from)?;
                 Ok(vec![])
             }
-            Err(crate::MessageError::Nack) => {
+            Err(crate::MessageError::Reject { requeue, .. }) => {
+                let opts = lapin::options::BasicRejectOptions { requeue };
                 self.rx
-                    .basic_nack(msg.delivery_tag(), self.nack_opts.clone())
+                    .basic_reject(msg.delivery_tag(), opts)
                     .await
                     .map_err(crate::Error::from)?;
                 Ok(vec![])
             }
+            Err(crate::MessageError::Nack {
+                requeue, multiple, ..
+            }) => {
+                let opts = lapin::options::BasicNackOptions { multiple, requeue };
+                self.rx
+                    .basic_nack(msg.delivery_tag(), opts)
+                    .await
+                    .map_err(crate::Error::from)?;
+                Ok(vec![])
+            }
+        }
+    }
+    #[cfg(feature = "encryption")]
+    fn encrypt(
+        &self,
+        props: lapin::BasicProperties,
+        msg: Vec<u8>,
+    ) -> crate::Result<(lapin::BasicProperties, Vec<u8>)> {
+        match &self.encryption {
+            Some(encryption) => encryption.encrypt(props, &msg),
+            None => Ok((props, msg)),
         }
     }
+    #[cfg(not(feature = "encryption"))]
+    fn encrypt(
+        &self,
+        props: lapin::BasicProperties,
+        msg: Vec<u8>,
+    ) -> crate::Result<(lapin::BasicProperties, Vec<u8>)> {
+        Ok((props, msg))
+    }
+    /// Decrypts the message in place and returns false on failure.
+    #[cfg(feature = "encryption")]
+    fn decrypt(&self, msg: &mut crate::Message) -> bool {
+        match &self.encryption {
+            Some(encryption) => match encryption.decrypt(msg) {
+                Some(data) => {
+                    msg.set_data(data);
+                    true
+                }
+                None => false,
+            },
+            None => true,
+        }
+    }
+    #[cfg(not(feature = "encryption"))]
+    fn decrypt(&self, _msg: &mut crate::Message) -> bool {
+        true
+    }
 }
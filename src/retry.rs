@@ -0,0 +1,338 @@
+// SPDX-License-Identifier: Apache-2.0 AND MIT
+//! `RetryPolicy` struct
+use std::convert::TryFrom;
+use std::time::Duration;
+
+/// The header name carrying the number of the retry attempts.
+pub const RETRY_COUNT_HEADER: &str = "x-retry-count";
+
+/// The maximum retry delay, bound by the 32 bits message TTL in
+/// milliseconds.
+pub const MAX_DELAY: Duration = Duration::from_millis(u32::MAX as u64);
+
+/// A retry policy for the messages failed to be processed by the
+/// [Consumer].
+///
+/// The failed message is republished to the per-delay retry queue, which
+/// dead-letters the message back to the original queue once the delay,
+/// the retry queue's message TTL, expires.  The message is republished to
+/// the parking queue, instead, once it runs out of the retry attempts.
+///
+/// The retried messages are delivered back with the queue name as the
+/// routing key, so that the [Verifier] accepts those only in case those
+/// are signed with the queue name, as the [Producer] does.
+///
+/// [Consumer]: ../consume/struct.Consumer.html
+/// [Verifier]: ../sign/struct.Verifier.html
+/// [Producer]: ../produce/struct.Producer.html
+#[derive(Clone)]
+pub struct RetryPolicy {
+    delays: Vec<Duration>,
+    parking_queue: Option<String>,
+}
+
+impl RetryPolicy {
+    /// Retry up to `max_attempts` times, doubling the delay on each
+    /// attempt starting from the `initial` delay, up to the [MAX_DELAY].
+    ///
+    /// [MAX_DELAY]: constant.MAX_DELAY.html
+    pub fn exponential(initial: Duration, max_attempts: u32) -> Self {
+        let delays = (0..max_attempts)
+            .map(|i| {
+                2u32.checked_pow(i)
+                    .and_then(|factor| initial.checked_mul(factor))
+                    .map_or(MAX_DELAY, |delay| delay.min(MAX_DELAY))
+            })
+            .collect();
+        Self {
+            delays,
+            parking_queue: None,
+        }
+    }
+    /// Retry once for each delay, in the order of the `delays`, each up to
+    /// the [MAX_DELAY].
+    ///
+    /// [MAX_DELAY]: constant.MAX_DELAY.html
+    pub fn delays(delays: &[Duration]) -> Self {
+        Self {
+            delays: delays.iter().map(|delay| *delay.min(&MAX_DELAY)).collect(),
+            parking_queue: None,
+        }
+    }
+    /// Specify the parking queue name.
+    ///
+    /// It's the original queue name followed by `.parking` by default.
+    pub fn parking_queue(&mut self, queue: &str) -> &mut Self {
+        self.parking_queue = Some(queue.to_string());
+        self
+    }
+    /// Declares the retry queues and the parking queue of the `queue`.
+    ///
+    /// Those are durable, and neither exclusive nor auto-deleted whatever
+    /// the `queue` is, so that the parked messages outlive the consumer.
+    pub(crate) async fn declare(&self, ch: &lapin::Channel, queue: &str) -> crate::Result<()> {
+        let opts = lapin::options::QueueDeclareOptions {
+            durable: true,
+            ..Default::default()
+        };
+        for delay in self.retry_delays() {
+            let ttl = u32::try_from(delay.as_millis()).unwrap_or(u32::MAX);
+            let mut args = lapin::types::FieldTable::default();
+            args.insert(
+                "x-message-ttl".into(),
+                lapin::types::AMQPValue::LongUInt(ttl),
+            );
+            args.insert(
+                "x-dead-letter-exchange".into(),
+                lapin::types::AMQPValue::LongString(crate::DEFAULT_EXCHANGE.into()),
+            );
+            args.insert(
+                "x-dead-letter-routing-key".into(),
+                lapin::types::AMQPValue::LongString(queue.into()),
+            );
+            ch.queue_declare(&Self::retry_queue(queue, delay), opts.clone(), args)
+                .await
+                .map_err(crate::Error::from)?;
+        }
+        ch.queue_declare(
+            &self.parking(queue),
+            opts,
+            lapin::types::FieldTable::default(),
+        )
+        .await
+        .map_err(crate::Error::from)?;
+        Ok(())
+    }
+    /// Returns the queue name, and the properties, to republish the failed
+    /// message through the default exchange.
+    pub(crate) fn next(
+        &self,
+        queue: &str,
+        msg: &crate::Message,
+    ) -> (String, lapin::BasicProperties) {
+        let attempts = Self::attempts(queue, msg);
+        let props = crate::message::with_header(
+            crate::dlq::origin_properties(msg, msg.properties().clone()),
+            RETRY_COUNT_HEADER,
+            lapin::types::AMQPValue::LongUInt(attempts + 1),
+        );
+        match self.delays.get(attempts as usize) {
+            Some(delay) => (Self::retry_queue(queue, *delay), props),
+            None => (self.parking(queue), props),
+        }
+    }
+    /// Returns the number of the retry attempts, counted by the custom
+    /// header or by the `x-death` header in case the custom header is
+    /// stripped.
+    fn attempts(queue: &str, msg: &crate::Message) -> u32 {
+        if let Some(count) = msg
+            .header_value(RETRY_COUNT_HEADER)
+            .and_then(crate::message::header_u64)
+        {
+            return count as u32;
+        }
+        let prefix = format!("{}.retry.", base(queue));
+        msg.deaths()
+            .iter()
+            .filter(|death| death.queue.starts_with(&prefix))
+            .map(|death| death.count as u32)
+            .sum()
+    }
+    /// Returns the distinct delays, one for each retry queue.
+    fn retry_delays(&self) -> Vec<Duration> {
+        let mut delays = self.delays.clone();
+        delays.sort();
+        delays.dedup();
+        delays
+    }
+    fn retry_queue(queue: &str, delay: Duration) -> String {
+        format!("{}.retry.{}", base(queue), delay.as_millis())
+    }
+    fn parking(&self, queue: &str) -> String {
+        match &self.parking_queue {
+            Some(parking) => parking.clone(),
+            None => format!("{}.parking", base(queue)),
+        }
+    }
+}
+
+/// Returns the queue name without the `amq.` prefix of the server-named
+/// queues, as the broker refuses to declare the queues of the prefix.
+fn base(queue: &str) -> &str {
+    queue.strip_prefix("amq.").unwrap_or(queue)
+}
+
+/// Returns true when the `queue` is one of the retry queues.
+pub(crate) fn is_retry_queue(queue: &str) -> bool {
+    match queue.rfind(".retry.") {
+        Some(i) => queue[i + ".retry.".len()..].parse::<u64>().is_ok(),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lapin::types::{AMQPValue, FieldArray, FieldTable};
+    use std::time::Duration;
+    fn message(headers: FieldTable) -> crate::Message {
+        let props = lapin::BasicProperties::default().with_headers(headers);
+        crate::message::test_message(1, "", "", props, b"")
+    }
+    fn death(queue: &str, count: i64) -> AMQPValue {
+        let mut death = FieldTable::default();
+        death.insert("queue".into(), AMQPValue::LongString(queue.into()));
+        death.insert("reason".into(), AMQPValue::LongString("expired".into()));
+        death.insert("count".into(), AMQPValue::LongLongInt(count));
+        AMQPValue::FieldTable(death)
+    }
+    #[test]
+    fn next() {
+        struct Test {
+            name: &'static str,
+            headers: FieldTable,
+            want_queue: &'static str,
+            want_count: u32,
+        }
+        let mut retried = FieldTable::default();
+        retried.insert(super::RETRY_COUNT_HEADER.into(), AMQPValue::LongUInt(2));
+        let mut exhausted = FieldTable::default();
+        exhausted.insert(super::RETRY_COUNT_HEADER.into(), AMQPValue::LongUInt(3));
+        let mut died = FieldTable::default();
+        let deaths = vec![
+            death("q.retry.200", 1),
+            death("q.retry.100", 1),
+            death("other", 5),
+        ];
+        died.insert(
+            "x-death".into(),
+            AMQPValue::FieldArray(FieldArray::from(deaths)),
+        );
+        let tests = [
+            Test {
+                name: "first failure",
+                headers: FieldTable::default(),
+                want_queue: "q.retry.100",
+                want_count: 1,
+            },
+            Test {
+                name: "third failure",
+                headers: retried,
+                want_queue: "q.retry.400",
+                want_count: 3,
+            },
+            Test {
+                name: "third failure counted by x-death",
+                headers: died,
+                want_queue: "q.retry.400",
+                want_count: 3,
+            },
+            Test {
+                name: "exhausted",
+                headers: exhausted,
+                want_queue: "q.parking",
+                want_count: 4,
+            },
+        ];
+        let policy = super::RetryPolicy::exponential(Duration::from_millis(100), 3);
+        for t in &tests {
+            let msg = message(t.headers.clone());
+            let (queue, props) = policy.next("q", &msg);
+            assert_eq!(t.want_queue, queue, "{}", t.name);
+            let count = crate::message::header(&props, super::RETRY_COUNT_HEADER);
+            assert_eq!(
+                Some(&AMQPValue::LongUInt(t.want_count)),
+                count,
+                "{}",
+                t.name
+            );
+        }
+    }
+    #[test]
+    fn queue_names() {
+        struct Test {
+            name: &'static str,
+            queue: &'static str,
+            want_retry: &'static str,
+            want_parking: &'static str,
+        }
+        let tests = [
+            Test {
+                name: "named queue",
+                queue: "q",
+                want_retry: "q.retry.100",
+                want_parking: "q.parking",
+            },
+            Test {
+                name: "server-named queue",
+                queue: "amq.gen-abc",
+                want_retry: "gen-abc.retry.100",
+                want_parking: "gen-abc.parking",
+            },
+            Test {
+                name: "amq in the middle",
+                queue: "q.amq.x",
+                want_retry: "q.amq.x.retry.100",
+                want_parking: "q.amq.x.parking",
+            },
+        ];
+        let policy = super::RetryPolicy::delays(&[Duration::from_millis(100)]);
+        for t in &tests {
+            let msg = message(FieldTable::default());
+            let (queue, _) = policy.next(t.queue, &msg);
+            assert_eq!(t.want_retry, queue, "{}", t.name);
+            assert_eq!(t.want_parking, policy.parking(t.queue), "{}", t.name);
+        }
+    }
+    #[test]
+    fn is_retry_queue() {
+        assert!(super::is_retry_queue("q.retry.100"));
+        assert!(super::is_retry_queue("a.retry.b.retry.100"));
+        assert!(!super::is_retry_queue("q"));
+        assert!(!super::is_retry_queue("q.retry.parking"));
+        assert!(!super::is_retry_queue("q.parking"));
+    }
+    #[test]
+    fn retry_delays() {
+        struct Test {
+            name: &'static str,
+            policy: super::RetryPolicy,
+            want: Vec<Duration>,
+        }
+        let tests = [
+            Test {
+                name: "exponential",
+                policy: super::RetryPolicy::exponential(Duration::from_millis(100), 3),
+                want: vec![
+                    Duration::from_millis(100),
+                    Duration::from_millis(200),
+                    Duration::from_millis(400),
+                ],
+            },
+            Test {
+                name: "exponential overflow",
+                policy: super::RetryPolicy::exponential(Duration::from_secs(1), 40),
+                want: (0..23)
+                    .map(|i| Duration::from_secs(1 << i))
+                    .chain(std::iter::once(super::MAX_DELAY))
+                    .collect(),
+            },
+            Test {
+                name: "non-consecutive duplicates",
+                policy: super::RetryPolicy::delays(&[
+                    Duration::from_millis(200),
+                    Duration::from_millis(100),
+                    Duration::from_millis(200),
+                ]),
+                want: vec![Duration::from_millis(100), Duration::from_millis(200)],
+            },
+            Test {
+                name: "too long delay",
+                policy: super::RetryPolicy::delays(&[Duration::from_secs(u64::MAX)]),
+                want: vec![super::MAX_DELAY],
+            },
+        ];
+        for t in &tests {
+            assert_eq!(t.want, t.policy.retry_delays(), "{}", t.name);
+        }
+    }
+}
@@ -0,0 +1,341 @@
+// SPDX-License-Identifier: Apache-2.0 AND MIT
+//! `Router` struct for the message handler routing
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// A [MessageProcess] router, which dispatches the message to the handler
+/// of the first matching route, or to the fallback handler.
+///
+/// The message without the matching route nor the fallback handler is
+/// rejected, and replied to, with the [Status::NOT_FOUND] status.
+///
+/// # Examples
+///
+/// ```no_run
+/// use async_mq::message::EchoProcessor;
+/// use async_mq::route::Router;
+/// use std::sync::Arc;
+///
+/// let mut router = Router::new();
+/// router
+///     .topic("order.*.created", Arc::new(EchoProcessor {}))
+///     .message_type("refund", Arc::new(EchoProcessor {}))
+///     .header("x-region", "eu", Arc::new(EchoProcessor {}))
+///     .fallback(Arc::new(EchoProcessor {}));
+/// ```
+///
+/// [MessageProcess]: ../message/trait.MessageProcess.html
+/// [Status::NOT_FOUND]: ../rpc/struct.Status.html#associatedconstant.NOT_FOUND
+#[derive(Clone, Default)]
+pub struct Router {
+    routes: Vec<(Route, Arc<dyn crate::MessageProcess + Send + Sync>)>,
+    fallback: Option<Arc<dyn crate::MessageProcess + Send + Sync>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Route {
+    Topic(Vec<String>),
+    Header(String, String),
+    MessageType(String),
+}
+
+impl Route {
+    fn matches(&self, msg: &crate::Message) -> bool {
+        match self {
+            Self::Topic(pattern) => {
+                let pattern: Vec<_> = pattern.iter().map(String::as_str).collect();
+                topic_matches(&pattern, &words(msg.routing_key()))
+            }
+            Self::Header(key, value) => msg.header::<String>(key).as_ref() == Some(value),
+            Self::MessageType(kind) => msg.kind() == Some(kind.as_str()),
+        }
+    }
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Route the messages with the routing key matching the AMQP topic
+    /// `pattern`, where `*` matches exactly one word and `#` matches zero
+    /// or more words.
+    pub fn topic(
+        &mut self,
+        pattern: &str,
+        handler: Arc<dyn crate::MessageProcess + Send + Sync>,
+    ) -> &mut Self {
+        let pattern = words(pattern).into_iter().map(String::from).collect();
+        self.routes.push((Route::Topic(pattern), handler));
+        self
+    }
+    /// Route the messages with the `key` header of the `value`.
+    pub fn header(
+        &mut self,
+        key: &str,
+        value: &str,
+        handler: Arc<dyn crate::MessageProcess + Send + Sync>,
+    ) -> &mut Self {
+        let route = Route::Header(key.to_string(), value.to_string());
+        self.routes.push((route, handler));
+        self
+    }
+    /// Route the messages of the `kind` type, the AMQP `type` property.
+    pub fn message_type(
+        &mut self,
+        kind: &str,
+        handler: Arc<dyn crate::MessageProcess + Send + Sync>,
+    ) -> &mut Self {
+        self.routes
+            .push((Route::MessageType(kind.to_string()), handler));
+        self
+    }
+    /// Use the provided handler for the messages without the matching route.
+    pub fn fallback(&mut self, handler: Arc<dyn crate::MessageProcess + Send + Sync>) -> &mut Self {
+        self.fallback = Some(handler);
+        self
+    }
+    /// Returns the index of the first matching route.
+    fn route(&self, msg: &crate::Message) -> Option<usize> {
+        self.routes.iter().position(|(route, _)| route.matches(msg))
+    }
+    fn handler(
+        &self,
+        msg: &crate::Message,
+    ) -> Result<&Arc<dyn crate::MessageProcess + Send + Sync>, crate::MessageError> {
+        match self.route(msg) {
+            Some(i) => Ok(&self.routes[i].1),
+            None => self.fallback.as_ref().ok_or_else(|| {
+                crate::MessageError::reject()
+                    .with_status(crate::rpc::Status::NOT_FOUND)
+                    .with_reason(&format!("no route for {}", msg.routing_key()))
+            }),
+        }
+    }
+}
+
+#[async_trait]
+impl crate::MessageProcess for Router {
+    async fn process(&self, msg: &crate::Message) -> Result<Vec<u8>, crate::MessageError> {
+        self.handler(msg)?.process(msg).await
+    }
+    async fn process_reply(
+        &self,
+        msg: &crate::Message,
+    ) -> Result<crate::Reply, crate::MessageError> {
+        self.handler(msg)?.process_reply(msg).await
+    }
+}
+
+/// Returns the dot separated words, where the empty string has no words
+/// as RabbitMQ does.
+fn words(s: &str) -> Vec<&str> {
+    if s.is_empty() {
+        Vec::new()
+    } else {
+        s.split('.').collect()
+    }
+}
+
+/// Returns true if the routing `key` words match the topic `pattern` words.
+///
+/// `matched[j]` tells whether the pattern words so far match the first `j`
+/// key words, so that it takes `O(pattern * key)` steps whatever the
+/// number of the `#` wildcards.
+fn topic_matches(pattern: &[&str], key: &[&str]) -> bool {
+    let mut matched = vec![false; key.len() + 1];
+    matched[0] = true;
+    for word in pattern {
+        if *word == "#" {
+            for j in 1..=key.len() {
+                matched[j] = matched[j] || matched[j - 1];
+            }
+        } else {
+            for j in (1..=key.len()).rev() {
+                matched[j] = matched[j - 1] && (*word == "*" || word == &key[j - 1]);
+            }
+            matched[0] = false;
+        }
+    }
+    matched[key.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::message::EchoProcessor;
+    use std::sync::Arc;
+    #[test]
+    fn topic_matches() {
+        struct Test {
+            name: &'static str,
+            pattern: &'static str,
+            key: &'static str,
+            want: bool,
+        }
+        let tests = [
+            Test {
+                name: "exact match",
+                pattern: "order.created",
+                key: "order.created",
+                want: true,
+            },
+            Test {
+                name: "exact mismatch",
+                pattern: "order.created",
+                key: "order.deleted",
+                want: false,
+            },
+            Test {
+                name: "star matches one word",
+                pattern: "order.*.created",
+                key: "order.eu.created",
+                want: true,
+            },
+            Test {
+                name: "star doesn't match zero words",
+                pattern: "order.*.created",
+                key: "order.created",
+                want: false,
+            },
+            Test {
+                name: "star doesn't match two words",
+                pattern: "order.*",
+                key: "order.eu.created",
+                want: false,
+            },
+            Test {
+                name: "hash matches zero words",
+                pattern: "order.#",
+                key: "order",
+                want: true,
+            },
+            Test {
+                name: "hash matches many words",
+                pattern: "order.#.created",
+                key: "order.eu.west.created",
+                want: true,
+            },
+            Test {
+                name: "hash alone matches everything",
+                pattern: "#",
+                key: "order.eu.created",
+                want: true,
+            },
+            Test {
+                name: "hash and star",
+                pattern: "#.*",
+                key: "order",
+                want: true,
+            },
+            Test {
+                name: "hash and star without words",
+                pattern: "#.*",
+                key: "",
+                want: false,
+            },
+            Test {
+                name: "hash alone matches no words",
+                pattern: "#",
+                key: "",
+                want: true,
+            },
+            Test {
+                name: "empty word",
+                pattern: "order.*.created",
+                key: "order..created",
+                want: true,
+            },
+            Test {
+                name: "many hashes",
+                pattern: "#.#.#.#.#.#.#.#.#.#.#.#.#.#.#.#.#.#.#.#.x",
+                key: "a.a.a.a.a.a.a.a.a.a.a.a.a.a.a.a.a.a.a.a.a.a.a.a.a.a.a.a.a.a",
+                want: false,
+            },
+            Test {
+                name: "longer key",
+                pattern: "order.created",
+                key: "order.created.eu",
+                want: false,
+            },
+        ];
+        for t in &tests {
+            let pattern = super::words(t.pattern);
+            let key = super::words(t.key);
+            let got = super::topic_matches(&pattern, &key);
+            assert_eq!(t.want, got, "{}", t.name);
+        }
+    }
+    #[test]
+    fn route() {
+        struct Test {
+            name: &'static str,
+            routing_key: &'static str,
+            kind: Option<&'static str>,
+            headers: Vec<(&'static str, &'static str)>,
+            want: Option<usize>,
+        }
+        let tests = [
+            Test {
+                name: "topic",
+                routing_key: "order.eu.created",
+                kind: None,
+                headers: vec![],
+                want: Some(0),
+            },
+            Test {
+                name: "message type",
+                routing_key: "payment",
+                kind: Some("refund"),
+                headers: vec![],
+                want: Some(1),
+            },
+            Test {
+                name: "header",
+                routing_key: "payment",
+                kind: None,
+                headers: vec![("x-region", "eu")],
+                want: Some(2),
+            },
+            Test {
+                name: "first matching route",
+                routing_key: "order.eu.created",
+                kind: Some("refund"),
+                headers: vec![],
+                want: Some(0),
+            },
+            Test {
+                name: "header mismatch",
+                routing_key: "payment",
+                kind: None,
+                headers: vec![("x-region", "us")],
+                want: None,
+            },
+            Test {
+                name: "no route",
+                routing_key: "order.created",
+                kind: None,
+                headers: vec![],
+                want: None,
+            },
+        ];
+        let mut router = super::Router::new();
+        router
+            .topic("order.*.created", Arc::new(EchoProcessor {}))
+            .message_type("refund", Arc::new(EchoProcessor {}))
+            .header("x-region", "eu", Arc::new(EchoProcessor {}));
+        for t in &tests {
+            let mut props = lapin::BasicProperties::default();
+            if let Some(kind) = t.kind {
+                props = props.with_type(kind.into());
+            }
+            for (key, value) in &t.headers {
+                props = crate::message::with_header(
+                    props,
+                    key,
+                    lapin::types::AMQPValue::LongString((*value).into()),
+                );
+            }
+            let msg = crate::message::test_message(1, "", t.routing_key, props, b"");
+            assert_eq!(t.want, router.route(&msg), "{}", t.name);
+        }
+    }
+}
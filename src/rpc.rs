@@ -0,0 +1,174 @@
+// SPDX-License-Identifier: Apache-2.0 AND MIT
+//! `Status` and `RemoteError` structs for the RPC response envelope
+//!
+//! The RPC reply carries the status code in the `x-status` header and the
+//! error message in the `x-error-reason` header, along with the body.  The
+//! reply without the status header is treated as a success so that the
+//! replies from the other AMQP RPC servers are accepted as is.
+use crate::message::ERROR_REASON_HEADER;
+
+/// The header name carrying the RPC status code.
+pub const STATUS_HEADER: &str = "x-status";
+
+/// An RPC status code, following the HTTP status code convention.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Status(pub u16);
+
+impl Status {
+    pub const OK: Self = Self(200);
+    pub const BAD_REQUEST: Self = Self(400);
+    pub const NOT_FOUND: Self = Self(404);
+    pub const INTERNAL_ERROR: Self = Self(500);
+    pub const UNAVAILABLE: Self = Self(503);
+    /// Returns true in case of the 2xx status code.
+    #[inline]
+    pub fn is_success(self) -> bool {
+        (200..300).contains(&self.0)
+    }
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A remote application error returned by the RPC server, as opposed to
+/// the transport errors.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RemoteError {
+    status: Status,
+    message: Option<String>,
+    body: Vec<u8>,
+}
+
+impl RemoteError {
+    #[inline]
+    pub fn status(&self) -> Status {
+        self.status
+    }
+    #[inline]
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+    /// Returns the error reply body.
+    #[inline]
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+}
+
+impl std::fmt::Display for RemoteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.message {
+            Some(message) => write!(f, "remote error {}: {}", self.status, message),
+            None => write!(f, "remote error {}", self.status),
+        }
+    }
+}
+
+/// Returns the properties with the envelope headers set.
+pub(crate) fn envelope(
+    props: lapin::BasicProperties,
+    status: Status,
+    message: Option<&str>,
+) -> lapin::BasicProperties {
+    let props = crate::message::with_header(
+        props,
+        STATUS_HEADER,
+        lapin::types::AMQPValue::ShortUInt(status.0),
+    );
+    match message {
+        Some(message) => crate::message::with_header(
+            props,
+            ERROR_REASON_HEADER,
+            lapin::types::AMQPValue::LongString(message.into()),
+        ),
+        None => props,
+    }
+}
+
+/// Returns the [RemoteError] in case the reply carries the error status.
+///
+/// [RemoteError]: struct.RemoteError.html
+pub(crate) fn check(msg: &crate::Message) -> Result<(), RemoteError> {
+    let status = match msg.header::<u16>(STATUS_HEADER) {
+        Some(status) => Status(status),
+        None => return Ok(()),
+    };
+    if status.is_success() {
+        return Ok(());
+    }
+    Err(RemoteError {
+        status,
+        message: msg.header::<String>(ERROR_REASON_HEADER),
+        body: msg.data().to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RemoteError, Status};
+    fn message(props: lapin::BasicProperties, data: &[u8]) -> crate::Message {
+        crate::message::test_message(1, "", "", props, data)
+    }
+    #[test]
+    fn reply_then_check() {
+        struct Test {
+            name: &'static str,
+            reply: Result<crate::Reply, crate::MessageError>,
+            want: Result<(), RemoteError>,
+        }
+        let tests = [
+            Test {
+                name: "success",
+                reply: Ok(crate::Reply::new(b"data".to_vec())),
+                want: Ok(()),
+            },
+            Test {
+                name: "application status",
+                reply: Ok(crate::Reply::new(b"missing".to_vec()).with_status(Status::NOT_FOUND)),
+                want: Err(RemoteError {
+                    status: Status::NOT_FOUND,
+                    message: None,
+                    body: b"missing".to_vec(),
+                }),
+            },
+            Test {
+                name: "error with reason",
+                reply: Err(crate::MessageError::reject().with_reason("bad request")),
+                want: Err(RemoteError {
+                    status: Status::INTERNAL_ERROR,
+                    message: Some(String::from("bad request")),
+                    body: vec![],
+                }),
+            },
+            Test {
+                name: "error with status and reply",
+                reply: Err(crate::MessageError::nack()
+                    .with_status(Status::UNAVAILABLE)
+                    .with_reason("try later")
+                    .with_reply(b"busy".to_vec())),
+                want: Err(RemoteError {
+                    status: Status::UNAVAILABLE,
+                    message: Some(String::from("try later")),
+                    body: b"busy".to_vec(),
+                }),
+            },
+        ];
+        for t in &tests {
+            let props = lapin::BasicProperties::default();
+            let (props, data) = match &t.reply {
+                Ok(reply) => (reply.properties(props), reply.data().to_vec()),
+                Err(err) => (err.properties(props), err.reply().unwrap()),
+            };
+            let got = super::check(&message(props, &data));
+            assert_eq!(t.want, got, "{}", t.name);
+        }
+    }
+    #[test]
+    fn check_without_status() {
+        let msg = message(lapin::BasicProperties::default(), b"foreign reply");
+        assert_eq!(Ok(()), super::check(&msg));
+    }
+}
@@ -0,0 +1,167 @@
+// SPDX-License-Identifier: Apache-2.0 AND MIT
+//! `Upcasters` struct for the message schema versioning
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// The header name carrying the schema version of the message payload.
+pub const SCHEMA_VERSION_HEADER: &str = "x-schema-version";
+
+/// The schema version of the messages without the schema version header.
+pub const INITIAL_VERSION: u32 = 1;
+
+type Upcaster = Arc<dyn Fn(Vec<u8>) -> Result<Vec<u8>, crate::MessageError> + Send + Sync>;
+
+/// An upcaster chain, which converts the older message payloads to the
+/// current schema version one version at a time.
+///
+/// The messages of the newer schema version than the current one, or the
+/// ones missing the upcaster of any version in between, are rejected.
+///
+/// # Examples
+///
+/// ```no_run
+/// use async_mq::schema::Upcasters;
+///
+/// let mut upcasters = Upcasters::new(3);
+/// upcasters
+///     .step(1, |data| Ok([b"v2:".as_ref(), &data].concat()))
+///     .step(2, |data| Ok([b"v3:".as_ref(), &data].concat()));
+/// ```
+#[derive(Clone)]
+pub struct Upcasters {
+    current: u32,
+    steps: BTreeMap<u32, Upcaster>,
+}
+
+impl Upcasters {
+    /// Returns the upcaster chain to the `current` schema version.
+    pub fn new(current: u32) -> Self {
+        Self {
+            current,
+            steps: BTreeMap::new(),
+        }
+    }
+    /// Register the upcaster from the `from` version to the next version.
+    pub fn step<F>(&mut self, from: u32, f: F) -> &mut Self
+    where
+        F: Fn(Vec<u8>) -> Result<Vec<u8>, crate::MessageError> + Send + Sync + 'static,
+    {
+        self.steps.insert(from, Arc::new(f));
+        self
+    }
+    /// Returns the current schema version.
+    #[inline]
+    pub fn current(&self) -> u32 {
+        self.current
+    }
+    /// Upcasts the message payload, as well as its schema version header,
+    /// to the current schema version in place.
+    pub fn upcast(&self, msg: &mut crate::Message) -> Result<(), crate::MessageError> {
+        let version = msg
+            .header::<u32>(SCHEMA_VERSION_HEADER)
+            .unwrap_or(INITIAL_VERSION);
+        if version == self.current {
+            return Ok(());
+        }
+        let data = self.upcast_data(version, msg.data().to_vec())?;
+        msg.set_data(data);
+        msg.set_header(
+            SCHEMA_VERSION_HEADER,
+            lapin::types::AMQPValue::LongUInt(self.current),
+        );
+        Ok(())
+    }
+    fn upcast_data(&self, version: u32, data: Vec<u8>) -> Result<Vec<u8>, crate::MessageError> {
+        if version > self.current {
+            let reason = format!("unsupported schema version: {}", version);
+            return Err(crate::MessageError::reject().with_reason(&reason));
+        }
+        let mut data = data;
+        for from in version..self.current {
+            data = match self.steps.get(&from) {
+                Some(upcaster) => upcaster(data)?,
+                None => {
+                    let reason = format!("missing upcaster of schema version: {}", from);
+                    return Err(crate::MessageError::reject().with_reason(&reason));
+                }
+            };
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn upcast() {
+        struct Test {
+            name: &'static str,
+            version: Option<u32>,
+            data: &'static [u8],
+            want: Result<&'static [u8], crate::MessageError>,
+        }
+        let tests = [
+            Test {
+                name: "current version",
+                version: Some(3),
+                data: b"data",
+                want: Ok(b"data"),
+            },
+            Test {
+                name: "initial version without header",
+                version: None,
+                data: b"data",
+                want: Ok(b"v3:v2:data"),
+            },
+            Test {
+                name: "one version behind",
+                version: Some(2),
+                data: b"data",
+                want: Ok(b"v3:data"),
+            },
+            Test {
+                name: "newer version",
+                version: Some(4),
+                data: b"data",
+                want: Err(
+                    crate::MessageError::reject().with_reason("unsupported schema version: 4")
+                ),
+            },
+            Test {
+                name: "missing upcaster",
+                version: Some(0),
+                data: b"data",
+                want: Err(crate::MessageError::reject()
+                    .with_reason("missing upcaster of schema version: 0")),
+            },
+        ];
+        let mut upcasters = super::Upcasters::new(3);
+        upcasters
+            .step(1, |data| Ok([b"v2:".as_ref(), &data].concat()))
+            .step(2, |data| Ok([b"v3:".as_ref(), &data].concat()));
+        for t in &tests {
+            let mut props = lapin::BasicProperties::default();
+            if let Some(version) = t.version {
+                props = crate::message::with_header(
+                    props,
+                    super::SCHEMA_VERSION_HEADER,
+                    lapin::types::AMQPValue::LongUInt(version),
+                );
+            }
+            let mut msg = crate::message::test_message(1, "", "", props, t.data);
+            match (&t.want, upcasters.upcast(&mut msg)) {
+                (Ok(want), Ok(())) => {
+                    assert_eq!(want, &msg.data(), "{}", t.name);
+                    assert_eq!(
+                        Some(3),
+                        msg.header::<u32>(super::SCHEMA_VERSION_HEADER),
+                        "{}",
+                        t.name
+                    );
+                }
+                (Err(want), Err(got)) => assert_eq!(want, &got, "{}", t.name),
+                (want, got) => panic!("{}: want {:?}, got {:?}", t.name, want, got),
+            }
+        }
+    }
+}
@@ -0,0 +1,252 @@
+// SPDX-License-Identifier: Apache-2.0 AND MIT
+//! `ServiceProcessor` and `RpcService` structs for the [tower] integration
+//!
+//! [tower]: https://docs.rs/tower
+use futures::future::{self, BoxFuture};
+use futures::lock::Mutex;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tower_service::Service;
+
+/// A [MessageProcess] adapter over the [tower::Service], so that the
+/// existing tower layers, e.g. timeouts and rate limiting, are reused
+/// by the [Consumer].
+///
+/// The service is kept behind the [Mutex], which is held while the
+/// service is polled for the readiness and called with the copy of the
+/// message, but not while the response is awaited.  The service state,
+/// e.g. the rate limiter's, and its backpressure are shared by the
+/// in-flight messages that way, without requiring the [Buffer].  The
+/// response is converted through [IntoReply].
+///
+/// [MessageProcess]: ../message/trait.MessageProcess.html
+/// [tower::Service]: https://docs.rs/tower/latest/tower/trait.Service.html
+/// [Consumer]: ../consume/struct.Consumer.html
+/// [IntoReply]: ../handler/trait.IntoReply.html
+/// [Mutex]: https://docs.rs/futures/latest/futures/lock/struct.Mutex.html
+/// [Buffer]: https://docs.rs/tower/latest/tower/buffer/struct.Buffer.html
+pub struct ServiceProcessor<S> {
+    service: Mutex<S>,
+}
+
+impl<S> ServiceProcessor<S> {
+    pub fn new(service: S) -> Self {
+        Self {
+            service: Mutex::new(service),
+        }
+    }
+    pub fn into_inner(self) -> S {
+        self.service.into_inner()
+    }
+}
+
+#[async_trait::async_trait]
+impl<S> crate::MessageProcess for ServiceProcessor<S>
+where
+    S: Service<crate::Message> + Send + 'static,
+    S::Response: crate::handler::IntoReply,
+    S::Error: Into<crate::MessageError>,
+    S::Future: Send,
+{
+    async fn process(&self, msg: &crate::Message) -> Result<Vec<u8>, crate::MessageError> {
+        self.process_reply(msg)
+            .await
+            .map(|reply| reply.data().to_vec())
+    }
+    async fn process_reply(
+        &self,
+        msg: &crate::Message,
+    ) -> Result<crate::Reply, crate::MessageError> {
+        use crate::handler::IntoReply;
+        let resp = {
+            let mut service = self.service.lock().await;
+            future::poll_fn(|cx| service.poll_ready(cx))
+                .await
+                .map_err(Into::into)?;
+            service.call(msg.detach())
+        };
+        match resp.await {
+            Ok(resp) => resp.into_reply(),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+/// A [tower::Service] adapter over the [Producer::rpc] method, so that
+/// the existing tower layers wrap the RPC requests.
+///
+/// The requests are sent one at a time, as the [Producer] waits for the
+/// reply of the request on its reply queue.
+///
+/// [tower::Service]: https://docs.rs/tower/latest/tower/trait.Service.html
+/// [Producer]: ../produce/struct.Producer.html
+/// [Producer::rpc]: ../produce/struct.Producer.html#method.rpc
+#[derive(Clone)]
+pub struct RpcService {
+    producer: Arc<Mutex<crate::Producer>>,
+}
+
+impl RpcService {
+    pub fn new(producer: crate::Producer) -> Self {
+        Self {
+            producer: Arc::new(Mutex::new(producer)),
+        }
+    }
+}
+
+impl Service<Vec<u8>> for RpcService {
+    type Response = Vec<u8>;
+    type Error = crate::Error;
+    type Future = BoxFuture<'static, crate::Result<Vec<u8>>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<crate::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+    fn call(&mut self, req: Vec<u8>) -> Self::Future {
+        let producer = self.producer.clone();
+        Box::pin(async move { producer.lock().await.rpc(req).await })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MessageError;
+    #[derive(Clone)]
+    struct Upper {
+        ready: bool,
+    }
+    impl Service<crate::Message> for Upper {
+        type Response = Vec<u8>;
+        type Error = MessageError;
+        type Future = future::Ready<Result<Vec<u8>, MessageError>>;
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), MessageError>> {
+            if self.ready {
+                Poll::Ready(Ok(()))
+            } else {
+                Poll::Ready(Err(MessageError::nack().with_requeue(true)))
+            }
+        }
+        fn call(&mut self, msg: crate::Message) -> Self::Future {
+            match msg.data() {
+                b"" => future::ready(Err(MessageError::reject().with_reason("empty"))),
+                data => future::ready(Ok(data.to_ascii_uppercase())),
+            }
+        }
+    }
+    #[test]
+    fn process_reply() {
+        struct Test {
+            name: &'static str,
+            ready: bool,
+            data: &'static [u8],
+            want: Result<crate::Reply, MessageError>,
+        }
+        let tests = [
+            Test {
+                name: "response",
+                ready: true,
+                data: b"data",
+                want: Ok(crate::Reply::new(b"DATA".to_vec())),
+            },
+            Test {
+                name: "call error",
+                ready: true,
+                data: b"",
+                want: Err(MessageError::reject().with_reason("empty")),
+            },
+            Test {
+                name: "not ready",
+                ready: false,
+                data: b"data",
+                want: Err(MessageError::nack().with_requeue(true)),
+            },
+        ];
+        for t in &tests {
+            let processor = ServiceProcessor::new(Upper { ready: t.ready });
+            let props = lapin::BasicProperties::default();
+            let msg = crate::message::test_message(1, "", "", props, t.data);
+            let got =
+                futures::executor::block_on(crate::MessageProcess::process_reply(&processor, &msg));
+            assert_eq!(t.want, got, "{}", t.name);
+        }
+    }
+    /// A test service over the tower middleware, ready or pending.
+    #[derive(Clone)]
+    struct Echo {
+        ready: bool,
+    }
+    impl Service<crate::Message> for Echo {
+        type Response = Vec<u8>;
+        type Error = Box<dyn std::error::Error + Send + Sync>;
+        type Future = future::Ready<Result<Vec<u8>, Self::Error>>;
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            if self.ready {
+                Poll::Ready(Ok(()))
+            } else {
+                Poll::Pending
+            }
+        }
+        fn call(&mut self, msg: crate::Message) -> Self::Future {
+            future::ready(Ok(msg.data().to_vec()))
+        }
+    }
+    #[test]
+    fn load_shed() {
+        struct Test {
+            name: &'static str,
+            ready: bool,
+            want: Result<crate::Reply, MessageError>,
+        }
+        let tests = [
+            Test {
+                name: "ready",
+                ready: true,
+                want: Ok(crate::Reply::new(b"data".to_vec())),
+            },
+            Test {
+                name: "overloaded",
+                ready: false,
+                want: Err(MessageError::reject()
+                    .with_status(crate::rpc::Status::INTERNAL_ERROR)
+                    .with_reason("service overloaded")),
+            },
+        ];
+        for t in &tests {
+            let service = tower_load_shed::LoadShed::new(Echo { ready: t.ready });
+            let processor = ServiceProcessor::new(service);
+            let props = lapin::BasicProperties::default();
+            let msg = crate::message::test_message(1, "", "", props, b"data");
+            let got =
+                futures::executor::block_on(crate::MessageProcess::process_reply(&processor, &msg));
+            assert_eq!(t.want, got, "{}", t.name);
+        }
+    }
+    /// A test service counting the calls.
+    struct Counter {
+        count: u32,
+    }
+    impl Service<crate::Message> for Counter {
+        type Response = String;
+        type Error = MessageError;
+        type Future = future::Ready<Result<String, MessageError>>;
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), MessageError>> {
+            Poll::Ready(Ok(()))
+        }
+        fn call(&mut self, _: crate::Message) -> Self::Future {
+            self.count += 1;
+            future::ready(Ok(self.count.to_string()))
+        }
+    }
+    #[test]
+    fn shared_service() {
+        let processor = ServiceProcessor::new(Counter { count: 0 });
+        let props = lapin::BasicProperties::default();
+        let msg = crate::message::test_message(1, "", "", props, b"");
+        for want in &[b"1", b"2", b"3"] {
+            let got = futures::executor::block_on(crate::MessageProcess::process(&processor, &msg));
+            assert_eq!(Ok(want.to_vec()), got);
+        }
+        assert_eq!(3, processor.into_inner().count);
+    }
+}
@@ -0,0 +1,445 @@
+// SPDX-License-Identifier: Apache-2.0 AND MIT
+//! `Signer` and `Verifier` HMAC structs
+use async_trait::async_trait;
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+use std::collections::HashMap;
+
+/// The header name carrying the hex encoded HMAC-SHA256 signature.
+pub const SIGNATURE_HEADER: &str = "x-signature";
+/// The header name carrying the key ID used to sign the message.
+pub const KEY_ID_HEADER: &str = "x-signature-key-id";
+/// The header name carrying the comma separated names of the signed headers.
+pub const SIGNED_HEADERS_HEADER: &str = "x-signature-headers";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// A HMAC-SHA256 message signer used by the [Producer].
+///
+/// The signature covers the routing key, the key ID, the content type
+/// and the headers present at the signing time, as well as the data,
+/// so that none of those can be altered or replayed under another
+/// routing key.  The headers added later, e.g. `x-death` by the broker,
+/// are not covered.
+///
+/// [Producer]: ../produce/struct.Producer.html
+#[derive(Clone)]
+pub struct Signer {
+    key_id: String,
+    key: Vec<u8>,
+}
+
+impl Signer {
+    pub fn new(key_id: &str, key: &[u8]) -> Self {
+        Self {
+            key_id: key_id.to_string(),
+            key: key.to_vec(),
+        }
+    }
+    /// Returns the key ID of the signer.
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+    /// Returns the properties with the signature, the key ID and the
+    /// signed headers set for the message published with the
+    /// `routing_key`.
+    pub(crate) fn sign(
+        &self,
+        routing_key: &str,
+        props: lapin::BasicProperties,
+        data: &[u8],
+    ) -> lapin::BasicProperties {
+        let names: Vec<String> = props
+            .headers()
+            .as_ref()
+            .map(|headers| {
+                headers
+                    .inner()
+                    .keys()
+                    .map(|name| name.as_str().to_string())
+                    .filter(|name| signed(name))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let props = crate::message::with_header(
+            props,
+            KEY_ID_HEADER,
+            lapin::types::AMQPValue::LongString(self.key_id.clone().into()),
+        );
+        let props = crate::message::with_header(
+            props,
+            SIGNED_HEADERS_HEADER,
+            lapin::types::AMQPValue::LongString(names.join(",").into()),
+        );
+        let input = canonical(routing_key, &props, &names).expect("signed headers");
+        let sig = hex::encode(mac(&self.key, &input, data).finalize().into_bytes());
+        crate::message::with_header(
+            props,
+            SIGNATURE_HEADER,
+            lapin::types::AMQPValue::LongString(sig.into()),
+        )
+    }
+}
+
+/// A [MessagePeek] implementation which rejects unsigned or tampered
+/// messages.
+///
+/// It holds multiple active keys, indexed by the key ID, so that the keys
+/// can be rotated without interrupting the producers.
+///
+/// [MessagePeek]: ../message/trait.MessagePeek.html
+#[derive(Clone, Default)]
+pub struct Verifier {
+    keys: HashMap<String, Vec<u8>>,
+}
+
+impl Verifier {
+    pub fn new() -> Self {
+        Self {
+            ..Default::default()
+        }
+    }
+    /// Add the active key.
+    pub fn key(&mut self, key_id: &str, key: &[u8]) -> &mut Self {
+        self.keys.insert(key_id.to_string(), key.to_vec());
+        self
+    }
+    /// Remove the retired key.
+    pub fn remove_key(&mut self, key_id: &str) -> &mut Self {
+        self.keys.remove(key_id);
+        self
+    }
+    /// Returns true when the message is signed by one of the active keys.
+    ///
+    /// The message is verified against the routing key it's delivered
+    /// with, as neither the `x-death` header nor the origin headers are
+    /// covered by the signature.  The dead-lettered messages are verified
+    /// once those are replayed with the original routing key by the
+    /// [DeadLetterQueue].
+    ///
+    /// [DeadLetterQueue]: ../dlq/struct.DeadLetterQueue.html
+    pub fn verify(&self, msg: &crate::Message) -> bool {
+        let key = match msg
+            .header_value(KEY_ID_HEADER)
+            .and_then(crate::message::header_str)
+            .and_then(|key_id| self.keys.get(key_id))
+        {
+            Some(key) => key,
+            None => return false,
+        };
+        let sig = match msg
+            .header_value(SIGNATURE_HEADER)
+            .and_then(crate::message::header_str)
+            .and_then(|sig| hex::decode(sig).ok())
+        {
+            Some(sig) => sig,
+            None => return false,
+        };
+        let names: Vec<String> = match msg
+            .header_value(SIGNED_HEADERS_HEADER)
+            .and_then(crate::message::header_str)
+        {
+            Some("") => vec![],
+            Some(names) => names.split(',').map(String::from).collect(),
+            None => return false,
+        };
+        match canonical(msg.routing_key(), msg.properties(), &names) {
+            Some(input) => mac(key, &input, msg.data()).verify(&sig).is_ok(),
+            None => false,
+        }
+    }
+}
+
+#[async_trait]
+impl crate::MessagePeek for Verifier {
+    /// Rejects the message which fails the signature verification.
+    async fn peek(&self, msg: &crate::Message) -> Result<(), crate::MessageError> {
+        if self.verify(msg) {
+            Ok(())
+        } else {
+            Err(crate::MessageError::reject().with_reason("invalid signature"))
+        }
+    }
+}
+
+/// Returns true when the header of the `name` is covered by the signature.
+fn signed(name: &str) -> bool {
+    name != SIGNATURE_HEADER
+        && name != KEY_ID_HEADER
+        && name != SIGNED_HEADERS_HEADER
+        && !name.contains(',')
+}
+
+/// Returns the canonical form of the routing key, the key ID, the content
+/// type and the signed headers, each prefixed by its length, or None when
+/// one of the signed headers is missing.
+fn canonical(
+    routing_key: &str,
+    props: &lapin::BasicProperties,
+    names: &[String],
+) -> Option<Vec<u8>> {
+    fn push(input: &mut Vec<u8>, field: &[u8]) {
+        input.extend_from_slice(&(field.len() as u64).to_be_bytes());
+        input.extend_from_slice(field);
+    }
+    let mut input = Vec::new();
+    push(&mut input, routing_key.as_bytes());
+    let key_id =
+        crate::message::header(props, KEY_ID_HEADER).and_then(crate::message::header_str)?;
+    push(&mut input, key_id.as_bytes());
+    let content_type = props
+        .content_type()
+        .as_ref()
+        .map(|s| s.as_str())
+        .unwrap_or("");
+    push(&mut input, content_type.as_bytes());
+    for name in names {
+        let value = crate::message::header(props, name)?;
+        push(&mut input, name.as_bytes());
+        push(&mut input, &header_bytes(value));
+    }
+    Some(input)
+}
+
+/// Returns the canonical bytes of the header value.
+fn header_bytes(value: &lapin::types::AMQPValue) -> Vec<u8> {
+    if let Some(s) = crate::message::header_str(value) {
+        return s.as_bytes().to_vec();
+    }
+    if let Some(v) = crate::message::header_u64(value) {
+        return v.to_string().into_bytes();
+    }
+    if let Some(v) = crate::message::header_i64(value) {
+        return v.to_string().into_bytes();
+    }
+    match value {
+        lapin::types::AMQPValue::Boolean(v) => v.to_string().into_bytes(),
+        value => format!("{:?}", value).into_bytes(),
+    }
+}
+
+fn mac(key: &[u8], input: &[u8], data: &[u8]) -> HmacSha256 {
+    // HMAC accepts the key of any length.
+    let mut mac = HmacSha256::new_varkey(key).expect("HMAC key");
+    mac.update(input);
+    mac.update(&(data.len() as u64).to_be_bytes());
+    mac.update(data);
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use lapin::types::{AMQPValue, FieldArray, FieldTable};
+    fn props() -> lapin::BasicProperties {
+        crate::message::with_header(
+            lapin::BasicProperties::default().with_content_type("text/plain".into()),
+            "x-tenant",
+            AMQPValue::LongString("a".into()),
+        )
+    }
+    #[test]
+    fn verify() {
+        struct Test {
+            name: &'static str,
+            sign_key_id: &'static str,
+            sign_key: &'static [u8],
+            routing_key: &'static str,
+            tamper: fn(lapin::BasicProperties) -> lapin::BasicProperties,
+            verify_routing_key: &'static str,
+            verify_data: &'static [u8],
+            want: bool,
+        }
+        let tests = [
+            Test {
+                name: "current key",
+                sign_key_id: "key2",
+                sign_key: b"secret2",
+                routing_key: "key",
+                tamper: |props| props,
+                verify_routing_key: "key",
+                verify_data: b"some data",
+                want: true,
+            },
+            Test {
+                name: "rotated key",
+                sign_key_id: "key1",
+                sign_key: b"secret1",
+                routing_key: "key",
+                tamper: |props| props,
+                verify_routing_key: "key",
+                verify_data: b"some data",
+                want: true,
+            },
+            Test {
+                name: "unknown key",
+                sign_key_id: "key3",
+                sign_key: b"secret3",
+                routing_key: "key",
+                tamper: |props| props,
+                verify_routing_key: "key",
+                verify_data: b"some data",
+                want: false,
+            },
+            Test {
+                name: "wrong key",
+                sign_key_id: "key1",
+                sign_key: b"secret2",
+                routing_key: "key",
+                tamper: |props| props,
+                verify_routing_key: "key",
+                verify_data: b"some data",
+                want: false,
+            },
+            Test {
+                name: "tampered data",
+                sign_key_id: "key2",
+                sign_key: b"secret2",
+                routing_key: "key",
+                tamper: |props| props,
+                verify_routing_key: "key",
+                verify_data: b"some other data",
+                want: false,
+            },
+            Test {
+                name: "replayed routing key",
+                sign_key_id: "key2",
+                sign_key: b"secret2",
+                routing_key: "key",
+                tamper: |props| props,
+                verify_routing_key: "other",
+                verify_data: b"some data",
+                want: false,
+            },
+            Test {
+                name: "tampered content type",
+                sign_key_id: "key2",
+                sign_key: b"secret2",
+                routing_key: "key",
+                tamper: |props| props.with_content_type("application/json".into()),
+                verify_routing_key: "key",
+                verify_data: b"some data",
+                want: false,
+            },
+            Test {
+                name: "tampered header",
+                sign_key_id: "key2",
+                sign_key: b"secret2",
+                routing_key: "key",
+                tamper: |props| {
+                    crate::message::with_header(
+                        props,
+                        "x-tenant",
+                        AMQPValue::LongString("b".into()),
+                    )
+                },
+                verify_routing_key: "key",
+                verify_data: b"some data",
+                want: false,
+            },
+            Test {
+                name: "tampered key ID",
+                sign_key_id: "key2",
+                sign_key: b"secret2",
+                routing_key: "key",
+                tamper: |props| {
+                    crate::message::with_header(
+                        props,
+                        super::KEY_ID_HEADER,
+                        AMQPValue::LongString("key1".into()),
+                    )
+                },
+                verify_routing_key: "key",
+                verify_data: b"some data",
+                want: false,
+            },
+            Test {
+                name: "removed signed header",
+                sign_key_id: "key2",
+                sign_key: b"secret2",
+                routing_key: "key",
+                tamper: |props| {
+                    let mut headers = props.headers().clone().unwrap_or_default();
+                    headers.insert(
+                        super::SIGNED_HEADERS_HEADER.into(),
+                        AMQPValue::LongString("".into()),
+                    );
+                    props.with_headers(headers)
+                },
+                verify_routing_key: "key",
+                verify_data: b"some data",
+                want: false,
+            },
+            Test {
+                name: "added unsigned header",
+                sign_key_id: "key2",
+                sign_key: b"secret2",
+                routing_key: "key",
+                tamper: |props| {
+                    crate::message::with_header(props, "x-retry-count", AMQPValue::LongUInt(1))
+                },
+                verify_routing_key: "key",
+                verify_data: b"some data",
+                want: true,
+            },
+            Test {
+                name: "forged x-death header",
+                sign_key_id: "key2",
+                sign_key: b"secret2",
+                routing_key: "key",
+                tamper: |props| {
+                    let mut death = FieldTable::default();
+                    death.insert("queue".into(), AMQPValue::LongString("queue".into()));
+                    death.insert("exchange".into(), AMQPValue::LongString("ex".into()));
+                    let keys = vec![AMQPValue::LongString("key".into())];
+                    death.insert(
+                        "routing-keys".into(),
+                        AMQPValue::FieldArray(FieldArray::from(keys)),
+                    );
+                    let deaths = vec![AMQPValue::FieldTable(death)];
+                    crate::message::with_header(
+                        props,
+                        "x-death",
+                        AMQPValue::FieldArray(FieldArray::from(deaths)),
+                    )
+                },
+                verify_routing_key: "dlq",
+                verify_data: b"some data",
+                want: false,
+            },
+            Test {
+                name: "forged origin header",
+                sign_key_id: "key2",
+                sign_key: b"secret2",
+                routing_key: "key",
+                tamper: |props| {
+                    crate::message::with_header(
+                        props,
+                        crate::dlq::ORIGIN_ROUTING_KEY_HEADER,
+                        AMQPValue::LongString("key".into()),
+                    )
+                },
+                verify_routing_key: "dlq",
+                verify_data: b"some data",
+                want: false,
+            },
+        ];
+        let mut verifier = super::Verifier::new();
+        verifier.key("key1", b"secret1").key("key2", b"secret2");
+        for t in &tests {
+            let signer = super::Signer::new(t.sign_key_id, t.sign_key);
+            let props = (t.tamper)(signer.sign(t.routing_key, props(), b"some data"));
+            let msg =
+                crate::message::test_message(1, "ex", t.verify_routing_key, props, t.verify_data);
+            assert_eq!(t.want, verifier.verify(&msg), "{}", t.name);
+        }
+    }
+    #[test]
+    fn verify_removed_key() {
+        let mut verifier = super::Verifier::new();
+        verifier.key("key1", b"secret1").key("key2", b"secret2");
+        verifier.remove_key("key1");
+        let props = super::Signer::new("key1", b"secret1").sign("key", props(), b"data");
+        let msg = crate::message::test_message(1, "", "key", props, b"data");
+        assert!(!verifier.verify(&msg));
+    }
+}
@@ -0,0 +1,186 @@
+// SPDX-License-Identifier: Apache-2.0 AND MIT
+//! Typed `Producer` and `Consumer` structs over the [Codec] trait
+//!
+//! [Codec]: ../codec/trait.Codec.html
+use std::marker::PhantomData;
+
+/// A typed [Producer] which encodes the messages of `T` with the [Codec]
+/// and sets the content type.
+///
+/// [Producer]: ../produce/struct.Producer.html
+/// [Codec]: ../codec/trait.Codec.html
+pub struct Producer<T, C> {
+    inner: crate::Producer,
+    codec: C,
+    _marker: PhantomData<fn(&T)>,
+}
+
+impl<T, C: crate::codec::Codec<T>> Producer<T, C> {
+    pub fn new(inner: crate::Producer, codec: C) -> Self {
+        Self {
+            inner,
+            codec,
+            _marker: PhantomData,
+        }
+    }
+    /// Returns the underlying [Producer].
+    ///
+    /// [Producer]: ../produce/struct.Producer.html
+    pub fn into_inner(self) -> crate::Producer {
+        self.inner
+    }
+    pub async fn publish(&mut self, value: &T) -> crate::Result<()> {
+        let msg = self.codec.encode(value)?;
+        let props = crate::codec::properties(&self.codec, self.inner.tx_props().clone());
+        self.inner.publish_with(props, msg).await
+    }
+    /// Send the RPC request and decode the reply into `R` with the same
+    /// [Codec].
+    ///
+    /// [Codec]: ../codec/trait.Codec.html
+    pub async fn rpc<R>(&mut self, value: &T) -> crate::Result<R>
+    where
+        C: crate::codec::Codec<R>,
+    {
+        let msg = crate::codec::Codec::<T>::encode(&self.codec, value)?;
+        let props = crate::codec::properties::<T, C>(&self.codec, self.inner.rx_props().clone());
+        let resp = self.inner.rpc_with(props, msg).await?;
+        crate::codec::Codec::<R>::decode(&self.codec, &resp)
+    }
+}
+
+/// A typed [Consumer] which decodes the messages into `T` with the
+/// [Codec].
+///
+/// The messages are peeked, reassembled, decrypted, upcasted and validated
+/// before decoded, the same way as the [Consumer::run] method does.  The
+/// messages failed to be decoded are rejected, which are dead-lettered
+/// in case the [dead-letter exchange] is configured.
+///
+/// [Consumer]: ../consume/struct.Consumer.html
+/// [Consumer::run]: ../consume/struct.Consumer.html#method.run
+/// [Codec]: ../codec/trait.Codec.html
+/// [dead-letter exchange]: ../consume/struct.ConsumerBuilder.html#method.dead_letter
+pub struct Consumer<T, C> {
+    inner: crate::Consumer,
+    codec: C,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T, C: crate::codec::Codec<T>> Consumer<T, C> {
+    pub fn new(inner: crate::Consumer, codec: C) -> Self {
+        Self {
+            inner,
+            codec,
+            _marker: PhantomData,
+        }
+    }
+    /// Returns the underlying [Consumer].
+    ///
+    /// [Consumer]: ../consume/struct.Consumer.html
+    pub fn into_inner(self) -> crate::Consumer {
+        self.inner
+    }
+    /// Returns the next decoded value, along with the message to reply to
+    /// or to acknowledge.
+    pub async fn next(&mut self) -> Option<crate::Result<(T, crate::Message)>> {
+        loop {
+            let msg = match self.inner.prepared().await? {
+                Ok(msg) => msg,
+                Err(err) => return Some(Err(err)),
+            };
+            match self.codec.decode(msg.data()) {
+                Ok(value) => return Some(Ok((value, msg))),
+                Err(_err) => {
+                    if let Err(err) = self.inner.reject(&msg).await {
+                        return Some(Err(err));
+                    }
+                }
+            }
+        }
+    }
+    /// Send the reply encoded with the same [Codec], and acknowledge the
+    /// request.
+    ///
+    /// [Codec]: ../codec/trait.Codec.html
+    pub async fn response<R>(&mut self, req: &crate::Message, resp: &R) -> crate::Result<()>
+    where
+        C: crate::codec::Codec<R>,
+    {
+        let data = crate::codec::Codec::<R>::encode(&self.codec, resp)?;
+        let content_type = crate::codec::Codec::<R>::content_type(&self.codec);
+        let mut reply = crate::Reply::new(data).with_content_type(content_type);
+        if let Some(message_type) = crate::codec::Codec::<R>::message_type(&self.codec) {
+            reply = reply.with_kind(message_type);
+        }
+        self.inner.reply(req, reply).await
+    }
+    pub async fn reject(&mut self, req: &crate::Message) -> crate::Result<()> {
+        self.inner.reject(req).await
+    }
+}
+
+/// A typed [Consumer] which decodes the requests into `T` with the codec
+/// [Registry] of the request's content type, and encodes the replies of
+/// `R` in the requester's content type.
+///
+/// The requests are prepared the same way as the [Consumer::run] method
+/// does.  The requests of the unknown content type are settled with the
+/// registry's unknown content type action, and the requests failed to
+/// be decoded are rejected.
+///
+/// [Consumer]: ../consume/struct.Consumer.html
+/// [Consumer::run]: ../consume/struct.Consumer.html#method.run
+/// [Registry]: ../codec/struct.Registry.html
+pub struct RegistryConsumer<T, R> {
+    inner: crate::Consumer,
+    requests: crate::codec::Registry<T>,
+    replies: crate::codec::Registry<R>,
+}
+
+impl<T, R> RegistryConsumer<T, R> {
+    pub fn new(
+        inner: crate::Consumer,
+        requests: crate::codec::Registry<T>,
+        replies: crate::codec::Registry<R>,
+    ) -> Self {
+        Self {
+            inner,
+            requests,
+            replies,
+        }
+    }
+    /// Returns the underlying [Consumer].
+    ///
+    /// [Consumer]: ../consume/struct.Consumer.html
+    pub fn into_inner(self) -> crate::Consumer {
+        self.inner
+    }
+    /// Returns the next decoded value, along with the message to reply to
+    /// or to acknowledge.
+    pub async fn next(&mut self) -> Option<crate::Result<(T, crate::Message)>> {
+        loop {
+            let msg = match self.inner.prepared().await? {
+                Ok(msg) => msg,
+                Err(err) => return Some(Err(err)),
+            };
+            match self.requests.decode(&msg) {
+                Ok(value) => return Some(Ok((value, msg))),
+                Err(action) => {
+                    if let Err(err) = self.inner.error(&msg, action).await {
+                        return Some(Err(err));
+                    }
+                }
+            }
+        }
+    }
+    /// Send the reply encoded in the requester's content type, and
+    /// acknowledge the request.
+    pub async fn response(&mut self, req: &crate::Message, resp: &R) -> crate::Result<()> {
+        let reply = self.replies.reply(req, resp)?;
+        self.inner.reply(req, reply).await
+    }
+    pub async fn reject(&mut self, req: &crate::Message) -> crate::Result<()> {
+        self.inner.reject(req).await
+    }
+}
@@ -0,0 +1,179 @@
+// SPDX-License-Identifier: Apache-2.0 AND MIT
+//! `Validator` struct for the JSON Schema validation
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// A JSON Schema validator of the message payloads.
+///
+/// The message is validated against the schema registered for its type,
+/// [Message::kind], or against the default schema, and
+/// rejected with the validation errors as the reason otherwise.  The
+/// messages without the applicable schema pass through.
+///
+/// It's either a [MessagePeek], which validates the raw payload, or the
+/// consumer stage through [ConsumerBuilder::validate], which validates the
+/// decrypted and upcasted payload.  Only the latter dead-letters the
+/// invalid messages with the validation errors in the
+/// [ERROR_REASON_HEADER] header, as the peeker failures are left to the
+/// broker's dead-lettering.
+///
+/// # Examples
+///
+/// ```no_run
+/// use async_mq::validate::Validator;
+/// use serde_json::json;
+///
+/// let mut validator = Validator::new();
+/// validator
+///     .message_type("order", &json!({"required": ["id"]}))
+///     .expect("invalid schema");
+/// ```
+///
+/// [Message::kind]: ../message/struct.Message.html#method.kind
+/// [ERROR_REASON_HEADER]: ../message/constant.ERROR_REASON_HEADER.html
+/// [MessagePeek]: ../message/trait.MessagePeek.html
+/// [ConsumerBuilder::validate]: ../consume/struct.ConsumerBuilder.html#method.validate
+#[derive(Clone, Default)]
+pub struct Validator {
+    schema: Option<Arc<jsonschema::JSONSchema>>,
+    types: HashMap<String, Arc<jsonschema::JSONSchema>>,
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Specify the default schema, e.g. the schema of the queue.
+    pub fn schema(&mut self, schema: &serde_json::Value) -> crate::Result<&mut Self> {
+        self.schema = Some(compile(schema)?);
+        Ok(self)
+    }
+    /// Specify the schema of the messages of the `kind` message type.
+    pub fn message_type(
+        &mut self,
+        kind: &str,
+        schema: &serde_json::Value,
+    ) -> crate::Result<&mut Self> {
+        self.types.insert(kind.to_string(), compile(schema)?);
+        Ok(self)
+    }
+    /// Validates the message payload against the applicable schema.
+    pub fn validate(&self, msg: &crate::Message) -> Result<(), crate::MessageError> {
+        let schema = match msg.kind().and_then(|kind| self.types.get(kind)) {
+            Some(schema) => schema,
+            None => match &self.schema {
+                Some(schema) => schema,
+                None => return Ok(()),
+            },
+        };
+        let instance: serde_json::Value = serde_json::from_slice(msg.data()).map_err(|err| {
+            crate::MessageError::reject().with_reason(&format!("invalid JSON: {}", err))
+        })?;
+        if let Err(errors) = schema.validate(&instance) {
+            let reason = errors
+                .map(|err| {
+                    let path = err.instance_path.to_string();
+                    let path = if path.is_empty() { "/" } else { &path };
+                    format!("{}: {}", path, err)
+                })
+                .collect::<Vec<_>>()
+                .join("; ");
+            return Err(crate::MessageError::reject().with_reason(&reason));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl crate::MessagePeek for Validator {
+    async fn peek(&self, msg: &crate::Message) -> Result<(), crate::MessageError> {
+        self.validate(msg)
+    }
+}
+
+fn compile(schema: &serde_json::Value) -> crate::Result<Arc<jsonschema::JSONSchema>> {
+    jsonschema::JSONSchema::compile(schema)
+        .map(Arc::new)
+        .map_err(|err| crate::Error::Codec(format!("invalid JSON schema: {}", err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    #[test]
+    fn validate() {
+        struct Test {
+            name: &'static str,
+            kind: Option<&'static str>,
+            data: &'static [u8],
+            want: Option<&'static str>,
+        }
+        let tests = [
+            Test {
+                name: "valid default schema",
+                kind: None,
+                data: br#"{"id": 1}"#,
+                want: None,
+            },
+            Test {
+                name: "invalid default schema",
+                kind: None,
+                data: br#"{"id": "one"}"#,
+                want: Some(r#"/id: "one" is not of type "integer""#),
+            },
+            Test {
+                name: "unknown message type with default schema",
+                kind: Some("unknown"),
+                data: br#"{}"#,
+                want: Some(r#"/: "id" is a required property"#),
+            },
+            Test {
+                name: "valid message type schema",
+                kind: Some("order"),
+                data: br#"{"id": 1, "items": ["a"]}"#,
+                want: None,
+            },
+            Test {
+                name: "invalid message type schema",
+                kind: Some("order"),
+                data: br#"{"id": 1, "items": []}"#,
+                want: Some("/items: [] has less than 1 item"),
+            },
+            Test {
+                name: "invalid JSON",
+                kind: None,
+                data: b"{",
+                want: Some("invalid JSON: EOF while parsing an object at line 1 column 1"),
+            },
+        ];
+        let mut validator = super::Validator::new();
+        validator
+            .schema(&json!({
+                "type": "object",
+                "required": ["id"],
+                "properties": {"id": {"type": "integer"}},
+            }))
+            .unwrap()
+            .message_type(
+                "order",
+                &json!({
+                    "type": "object",
+                    "properties": {"items": {"type": "array", "minItems": 1}},
+                }),
+            )
+            .unwrap();
+        for t in &tests {
+            let mut props = lapin::BasicProperties::default();
+            if let Some(kind) = t.kind {
+                props = props.with_type(kind.into());
+            }
+            let msg = crate::message::test_message(1, "", "", props, t.data);
+            let got = validator.validate(&msg).err();
+            let want = t
+                .want
+                .map(|reason| crate::MessageError::reject().with_reason(reason));
+            assert_eq!(want, got, "{}", t.name);
+        }
+    }
+}